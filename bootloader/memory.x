@@ -5,7 +5,7 @@ MEMORY
   MBR_SOFTDEVICE                    : ORIGIN = 0x00000000, LENGTH = 152K
   ACTIVE                            : ORIGIN = 0x00026000, LENGTH = 160K /* Location of the currently active firmware. Firmware always runs from this place. */
   DFU                               : ORIGIN = 0x0004E000, LENGTH = 164K /* Needs to be 1 page (4k) bigger than ACTIVE. Bootloader will swap the firmware from here into ACTIVE. */
-  CONFIG                            : ORIGIN = 0x00078000, LENGTH = 4K
+  CONFIG                            : ORIGIN = 0x00077000, LENGTH = 8K   /* Application config journal. Two pages, written alternately. */
   FLASH                             : ORIGIN = 0x00079000, LENGTH = 24K  /* In this case, FLASH is where we flash our bootloader. */
   BOOTLOADER_STATE                  : ORIGIN = 0x0007F000, LENGTH = 4K   /* Where the bootloader stores the current state describing if the active and dfu partitions need to be swapped. */
   RAM                         (rwx) : ORIGIN = 0x20002cd0, LENGTH = 32K
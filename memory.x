@@ -6,7 +6,7 @@ MEMORY
   MBR_SOFTDEVICE                    : ORIGIN = 0x00000000, LENGTH = 152K
   FLASH                             : ORIGIN = 0x00026000, LENGTH = 160K
   DFU                               : ORIGIN = 0x0004E000, LENGTH = 164K
-  CONFIG                            : ORIGIN = 0x00078000, LENGTH = 4K
+  CONFIG                            : ORIGIN = 0x00077000, LENGTH = 8K
   BOOTLOADER                        : ORIGIN = 0x00079000, LENGTH = 24K
   BOOTLOADER_STATE                  : ORIGIN = 0x0007F000, LENGTH = 4K
   RAM                         (rwx) : ORIGIN = 0x20002cd0, LENGTH = 32K
@@ -16,11 +16,12 @@ MEMORY
 SECTIONS
 {
     /* Here we store user config such as sample interval and advertisment name. */
+    /* Two pages, used alternately by the config journal. */
     .config_section :
     {
         /* Set the memory region to be initialized */
         __config_section_start__ = ADDR(.config_section);
-        __config_section_end__ = ADDR(.config_section) + 4K;
+        __config_section_end__ = ADDR(.config_section) + LENGTH(CONFIG);
     } > CONFIG
 }
 
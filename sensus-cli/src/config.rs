@@ -0,0 +1,168 @@
+//! Command line names for config fields and values.
+
+use std::fmt;
+use std::str::FromStr;
+
+use sensus_protocol::config::{CalibrationPoint, ConfigField, ConfigValue, ResetScope, Sensor};
+
+/// Names of the fields that don't take an index, in `ConfigField` order.
+const PLAIN_FIELDS: [(&str, ConfigField); 14] = [
+    ("onboard-sdt-plugged-ms", ConfigField::OnboardSdtPluggedMs),
+    ("probe-sdt-plugged-ms", ConfigField::ProbeSdtPluggedMs),
+    ("onboard-sdt-battery-ms", ConfigField::OnboardSdtBatteryMs),
+    ("probe-sdt-battery-ms", ConfigField::ProbeSdtBatteryMs),
+    ("name", ConfigField::Name),
+    ("adv-interval-plugged-ms", ConfigField::AdvIntervalPluggedMs),
+    ("adv-interval-battery-ms", ConfigField::AdvIntervalBatteryMs),
+    ("tx-power-plugged-dbm", ConfigField::TxPowerPluggedDbm),
+    ("tx-power-battery-dbm", ConfigField::TxPowerBatteryDbm),
+    (
+        "environment-settling-ms",
+        ConfigField::EnvironmentSettlingMs,
+    ),
+    ("battery-settling-ms", ConfigField::BatterySettlingMs),
+    ("probe-settling-ms", ConfigField::ProbeSettlingMs),
+    ("serial-frame-timeout-ms", ConfigField::SerialFrameTimeoutMs),
+    ("serial-byte-timeout-ms", ConfigField::SerialByteTimeoutMs),
+];
+
+const SENSORS: [(&str, Sensor); 4] = [
+    ("probe", Sensor::Probe),
+    ("opt3001", Sensor::Opt3001),
+    ("shtc3", Sensor::Shtc3),
+    ("battery", Sensor::Battery),
+];
+
+fn unknown_field(name: &str) -> String {
+    let mut names: Vec<&str> = PLAIN_FIELDS.iter().map(|(name, _)| *name).collect();
+    names.push("sensor.<probe|opt3001|shtc3|battery>");
+    names.push("calibration.<index>");
+    format!(
+        "unknown field `{name}`, expected one of: {}",
+        names.join(", ")
+    )
+}
+
+/// Parses a field name such as `name`, `sensor.probe` or `calibration.0`.
+pub fn parse_field(name: &str) -> Result<ConfigField, String> {
+    if let Some(index) = name.strip_prefix("calibration.") {
+        let index = index
+            .parse()
+            .map_err(|_| format!("invalid calibration index `{index}`"))?;
+        return Ok(ConfigField::CalibrationPoint(index));
+    }
+    if let Some(sensor) = name.strip_prefix("sensor.") {
+        return SENSORS
+            .iter()
+            .find(|(sensor_name, _)| *sensor_name == sensor)
+            .map(|(_, sensor)| ConfigField::SensorEnabled(*sensor))
+            .ok_or_else(|| unknown_field(name));
+    }
+
+    PLAIN_FIELDS
+        .iter()
+        .find(|(field_name, _)| *field_name == name)
+        .map(|(_, field)| *field)
+        .ok_or_else(|| unknown_field(name))
+}
+
+fn parse_number<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{value}` is not a valid number"))
+}
+
+/// Parses the value of the given field. Calibration points are given as `<frequency>:<percent>`.
+pub fn parse_value(field: ConfigField, value: &str) -> Result<ConfigValue, String> {
+    let value = match field {
+        ConfigField::OnboardSdtPluggedMs => ConfigValue::OnboardSdtPluggedMs(parse_number(value)?),
+        ConfigField::ProbeSdtPluggedMs => ConfigValue::ProbeSdtPluggedMs(parse_number(value)?),
+        ConfigField::OnboardSdtBatteryMs => ConfigValue::OnboardSdtBatteryMs(parse_number(value)?),
+        ConfigField::ProbeSdtBatteryMs => ConfigValue::ProbeSdtBatteryMs(parse_number(value)?),
+        ConfigField::Name => ConfigValue::Name(
+            heapless::String::from_str(value)
+                .map_err(|_| "the name can be at most 29 bytes long".to_string())?,
+        ),
+        ConfigField::CalibrationPoint(index) => {
+            let (frequency, percentage) = value
+                .split_once(':')
+                .ok_or_else(|| "expected `<frequency>:<percent>`".to_string())?;
+            ConfigValue::CalibrationPoint(
+                index,
+                CalibrationPoint {
+                    frequency: parse_number(frequency)?,
+                    percentage: parse_number(percentage)?,
+                },
+            )
+        }
+        ConfigField::SensorEnabled(sensor) => {
+            let enabled = match value {
+                "on" | "true" | "1" => true,
+                "off" | "false" | "0" => false,
+                _ => return Err(format!("expected `on` or `off`, got `{value}`")),
+            };
+            ConfigValue::SensorEnabled(sensor, enabled)
+        }
+        ConfigField::AdvIntervalPluggedMs => {
+            ConfigValue::AdvIntervalPluggedMs(parse_number(value)?)
+        }
+        ConfigField::AdvIntervalBatteryMs => {
+            ConfigValue::AdvIntervalBatteryMs(parse_number(value)?)
+        }
+        ConfigField::TxPowerPluggedDbm => ConfigValue::TxPowerPluggedDbm(parse_number(value)?),
+        ConfigField::TxPowerBatteryDbm => ConfigValue::TxPowerBatteryDbm(parse_number(value)?),
+        ConfigField::EnvironmentSettlingMs => {
+            ConfigValue::EnvironmentSettlingMs(parse_number(value)?)
+        }
+        ConfigField::BatterySettlingMs => ConfigValue::BatterySettlingMs(parse_number(value)?),
+        ConfigField::ProbeSettlingMs => ConfigValue::ProbeSettlingMs(parse_number(value)?),
+        ConfigField::SerialFrameTimeoutMs => {
+            ConfigValue::SerialFrameTimeoutMs(parse_number(value)?)
+        }
+        ConfigField::SerialByteTimeoutMs => ConfigValue::SerialByteTimeoutMs(parse_number(value)?),
+    };
+
+    Ok(value)
+}
+
+/// Parses `all`, `calibration` or `sampling-periods`.
+pub fn parse_reset_scope(scope: &str) -> Result<ResetScope, String> {
+    match scope {
+        "all" => Ok(ResetScope::All),
+        "calibration" => Ok(ResetScope::Calibration),
+        "sampling-periods" => Ok(ResetScope::SamplingPeriods),
+        _ => Err(format!(
+            "unknown scope `{scope}`, expected all, calibration or sampling-periods"
+        )),
+    }
+}
+
+/// Prints a value the way `parse_value` reads it back.
+pub struct DisplayValue<'a>(pub &'a ConfigValue);
+
+impl fmt::Display for DisplayValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            ConfigValue::OnboardSdtPluggedMs(value)
+            | ConfigValue::ProbeSdtPluggedMs(value)
+            | ConfigValue::OnboardSdtBatteryMs(value)
+            | ConfigValue::ProbeSdtBatteryMs(value)
+            | ConfigValue::AdvIntervalPluggedMs(value)
+            | ConfigValue::AdvIntervalBatteryMs(value)
+            | ConfigValue::EnvironmentSettlingMs(value)
+            | ConfigValue::BatterySettlingMs(value)
+            | ConfigValue::ProbeSettlingMs(value)
+            | ConfigValue::SerialFrameTimeoutMs(value)
+            | ConfigValue::SerialByteTimeoutMs(value) => write!(f, "{value}"),
+            ConfigValue::TxPowerPluggedDbm(value) | ConfigValue::TxPowerBatteryDbm(value) => {
+                write!(f, "{value}")
+            }
+            ConfigValue::Name(name) => write!(f, "{name}"),
+            ConfigValue::CalibrationPoint(_, point) => {
+                write!(f, "{}:{}", point.frequency, point.percentage)
+            }
+            ConfigValue::SensorEnabled(_, true) => write!(f, "on"),
+            ConfigValue::SensorEnabled(_, false) => write!(f, "off"),
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Firmware upload over the `DfuPayload` handshake.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use heapless::Vec as HVec;
+use ihex::Record;
+use sensus_protocol::comm::{CommEvent, CommMessage, CommPacketType, CommResponse, ResponseTypeOk};
+use sensus_protocol::dfu::{DfuBlock, DfuHeader, DfuPayload, DfuResponse, BLOCK_SIZE};
+
+use crate::link::{Error, Link};
+
+/// The firmware only writes whole flash pages, so images are padded to a multiple of this.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Loads a firmware image from a raw `.bin` or an Intel `.hex` file, padded to whole pages.
+pub fn load_image(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let mut image = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("hex") | Some("ihex") => {
+            let text = fs::read_to_string(path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            image_from_hex(&text)?
+        }
+        _ => fs::read(path).with_context(|| format!("failed to read {}", path.display()))?,
+    };
+
+    if image.is_empty() {
+        bail!("{} holds no data", path.display());
+    }
+
+    let padded_len = image.len().div_ceil(PAGE_SIZE) * PAGE_SIZE;
+    image.resize(padded_len, 0xFF);
+    Ok(image)
+}
+
+/// Flattens an Intel HEX file into a binary starting at its lowest address. Gaps are filled with
+/// 0xFF, just like erased flash.
+pub fn image_from_hex(text: &str) -> anyhow::Result<Vec<u8>> {
+    let mut base = 0u32;
+    let mut data = BTreeMap::new();
+    for record in ihex::Reader::new(text) {
+        match record.context("invalid HEX file")? {
+            Record::Data { offset, value } => {
+                data.insert(base + offset as u32, value);
+            }
+            Record::ExtendedSegmentAddress(segment) => base = (segment as u32) << 4,
+            Record::ExtendedLinearAddress(upper) => base = (upper as u32) << 16,
+            Record::EndOfFile => break,
+            Record::StartSegmentAddress { .. } | Record::StartLinearAddress(_) => {}
+        }
+    }
+
+    let Some(&start) = data.keys().next() else {
+        return Ok(Vec::new());
+    };
+    let mut image = Vec::new();
+    for (address, value) in data {
+        let offset = (address - start) as usize;
+        if offset < image.len() {
+            bail!("overlapping data at {address:#010x}");
+        }
+        image.resize(offset, 0xFF);
+        image.extend_from_slice(&value);
+    }
+
+    Ok(image)
+}
+
+/// Uploads `image` and waits for the device to confirm it. The device restarts into the new
+/// firmware right after. `progress` gets called with the number of blocks sent and the total.
+pub fn upload<P: Read + Write>(
+    link: &mut Link<P>,
+    image: &[u8],
+    mut progress: impl FnMut(usize, usize),
+) -> Result<(), Error> {
+    let blocks: Vec<&[u8]> = image.chunks(BLOCK_SIZE).collect();
+    let header = DfuHeader {
+        binary_size: image.len() as u32,
+        no_blocks: blocks.len() as u16,
+    };
+    link.send(CommPacketType::DfuPacket(DfuPayload::StartDfu(header)))?;
+
+    // From here on the device drives the transfer, asking for one block at a time.
+    loop {
+        match link.receive()? {
+            CommMessage::Response(_, CommResponse::Ok(ResponseTypeOk::Dfu(response))) => {
+                match response {
+                    DfuResponse::RequestBlock(idx) => {
+                        let block_idx = u16::from_le_bytes(idx);
+                        let Some(data) = blocks.get(block_idx as usize) else {
+                            return Err(Error::Unexpected(Box::new(ResponseTypeOk::Dfu(
+                                DfuResponse::RequestBlock(idx),
+                            ))));
+                        };
+                        let block = DfuBlock {
+                            block_idx,
+                            // Can't fail, the blocks are at most BLOCK_SIZE long.
+                            data: HVec::from_slice(data).unwrap(),
+                        };
+                        link.send(CommPacketType::DfuPacket(DfuPayload::Block(block)))?;
+                        progress(block_idx as usize + 1, blocks.len());
+                    }
+                    DfuResponse::DfuDone => return Ok(()),
+                    response => {
+                        return Err(Error::Unexpected(Box::new(ResponseTypeOk::Dfu(response))))
+                    }
+                }
+            }
+            CommMessage::Response(_, CommResponse::Ok(response)) => {
+                return Err(Error::Unexpected(Box::new(response)))
+            }
+            CommMessage::Response(_, CommResponse::Err(err)) => return Err(Error::Device(err)),
+            CommMessage::Event(CommEvent::PacketError(err)) => return Err(Error::Rejected(err)),
+            // Sensor data and the like, not our business.
+            CommMessage::Event(_) => {}
+        }
+    }
+}
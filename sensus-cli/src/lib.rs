@@ -0,0 +1,5 @@
+//! Host side of the Sensus serial protocol. The `sensus-cli` binary is a thin layer on top of this.
+
+pub mod config;
+pub mod dfu;
+pub mod link;
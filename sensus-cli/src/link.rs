@@ -0,0 +1,184 @@
+//! Request/response handling on top of a byte stream, usually a serial port.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use sensus_protocol::comm::{
+    CommEvent, CommMessage, CommPacket, CommPacketType, CommResponse, PacketError, RequestId,
+    ResponseTypeErr, ResponseTypeOk,
+};
+use sensus_protocol::fragment::{self, Fragment, Reassembler};
+use sensus_protocol::framing::{self, max_frame_size, FramingError};
+
+/// Frames are never larger than this, no matter the direction.
+const MAX_FRAME_SIZE: usize = max_frame_size(256);
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// Nothing arrived within the link's timeout.
+    Timeout,
+    /// A frame could not be encoded or decoded.
+    Framing(FramingError),
+    /// The device couldn't decode one of our packets, so the request was lost.
+    Rejected(PacketError),
+    /// The device answered with an error.
+    Device(ResponseTypeErr),
+    /// The device answered with something we didn't ask for.
+    Unexpected(Box<ResponseTypeOk>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {err}"),
+            Error::Timeout => write!(f, "timed out waiting for the device"),
+            Error::Framing(err) => write!(f, "framing error: {err:?}"),
+            Error::Rejected(err) => write!(f, "device rejected the packet: {err:?}"),
+            Error::Device(ResponseTypeErr::UnsupportedCommand(id)) => {
+                write!(f, "the device's firmware doesn't support message {id:#06x}")
+            }
+            Error::Device(err) => write!(f, "device returned an error: {err:?}"),
+            Error::Unexpected(response) => write!(f, "unexpected response: {response:?}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<FramingError> for Error {
+    fn from(err: FramingError) -> Self {
+        Error::Framing(err)
+    }
+}
+
+/// Sends requests to the device and pairs them with their responses.
+///
+/// The port may return `TimedOut` or `WouldBlock` when no data is available, as serial ports with
+/// a read timeout do. Events received while waiting for a response are kept for `next_event`.
+pub struct Link<P> {
+    port: P,
+    next_id: RequestId,
+    timeout: Duration,
+    /// Bytes read from the port which are not part of a complete frame yet.
+    rx_buf: Vec<u8>,
+    /// Messages from the device come in fragments.
+    reassembler: Reassembler,
+    events: VecDeque<CommEvent>,
+}
+
+impl<P: Read + Write> Link<P> {
+    pub fn new(port: P) -> Self {
+        Self {
+            port,
+            next_id: 0,
+            timeout: Duration::from_secs(1),
+            rx_buf: Vec::new(),
+            reassembler: Reassembler::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// How long to wait for a message before giving up.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends a request and waits for its response.
+    pub fn request(&mut self, payload: CommPacketType) -> Result<ResponseTypeOk, Error> {
+        let id = self.send(payload)?;
+        loop {
+            match self.receive()? {
+                CommMessage::Response(response_id, response) if response_id == id => {
+                    return match response {
+                        CommResponse::Ok(response) => Ok(response),
+                        CommResponse::Err(err) => Err(Error::Device(err)),
+                    };
+                }
+                // A late answer to a request we already gave up on.
+                CommMessage::Response(..) => continue,
+                CommMessage::Event(CommEvent::PacketError(err)) => {
+                    return Err(Error::Rejected(err))
+                }
+                CommMessage::Event(event) => self.events.push_back(event),
+            }
+        }
+    }
+
+    /// Sends a request without waiting for the response. Returns the ID it was sent with.
+    pub fn send(&mut self, payload: CommPacketType) -> Result<RequestId, Error> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let mut buf = [0u8; MAX_FRAME_SIZE];
+        let frame = framing::encode(&CommPacket { id, payload }, &mut buf)?;
+        self.port.write_all(frame)?;
+        self.port.flush()?;
+
+        Ok(id)
+    }
+
+    /// Waits for the next message, response or event.
+    pub fn receive(&mut self) -> Result<CommMessage, Error> {
+        loop {
+            let mut frame = self.read_frame()?;
+            let fragment: Fragment = framing::decode(&mut frame)?;
+            if let Some(message) = self.reassembler.push(fragment)? {
+                return Ok(fragment::decode(message)?);
+            }
+        }
+    }
+
+    /// Waits for the next event. Responses received in the meantime are dropped.
+    pub fn next_event(&mut self) -> Result<CommEvent, Error> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+
+        loop {
+            if let CommMessage::Event(event) = self.receive()? {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Reads until a 0x00 is found. Returns the frame, terminator included.
+    fn read_frame(&mut self) -> Result<Vec<u8>, Error> {
+        let deadline = Instant::now() + self.timeout;
+        let mut chunk = [0u8; 256];
+        loop {
+            if let Some(end) = self.rx_buf.iter().position(|&b| b == 0x00) {
+                let rest = self.rx_buf.split_off(end + 1);
+                return Ok(std::mem::replace(&mut self.rx_buf, rest));
+            }
+
+            if Instant::now() >= deadline {
+                return Err(Error::Timeout);
+            }
+
+            match self.port.read(&mut chunk) {
+                Ok(0) => {
+                    return Err(Error::Io(io::Error::from(io::ErrorKind::UnexpectedEof)));
+                }
+                Ok(n) => self.rx_buf.extend_from_slice(&chunk[..n]),
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::TimedOut
+                            | io::ErrorKind::WouldBlock
+                            | io::ErrorKind::Interrupted
+                    ) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
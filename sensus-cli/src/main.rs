@@ -0,0 +1,286 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand};
+use sensus_protocol::comm::{CommEvent, CommPacketType, ResponseTypeOk};
+use sensus_protocol::config::{ConfigField, ConfigPayload, ConfigResponse, ResetScope};
+use sensus_protocol::dfu::{DfuPayload, DfuResponse};
+use sensus_protocol::sensors::{OnboardSample, ProbeSample};
+use serialport::SerialPort;
+
+use sensus_cli::config::{self, DisplayValue};
+use sensus_cli::dfu;
+use sensus_cli::link::{Error, Link};
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Serial port Sensus is connected to, e.g. /dev/ttyUSB0.
+    #[arg(short, long, env = "SENSUS_PORT")]
+    port: String,
+    #[arg(short, long, default_value_t = 460800)]
+    baudrate: u32,
+    /// How long to wait for each answer, in milliseconds.
+    #[arg(short, long, default_value_t = 1000)]
+    timeout_ms: u64,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Prints the firmware, protocol and hardware details.
+    Info,
+    /// Prints the firmware version.
+    Version,
+    /// Prints the BLE MAC address.
+    Mac,
+    /// Reads or changes the config.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Prints the latest sensor data.
+    Data {
+        /// Keep printing every new sample, raw and filtered, instead of polling once.
+        #[arg(short, long)]
+        stream: bool,
+        /// Poll again every this many seconds.
+        #[arg(short, long, conflicts_with = "stream")]
+        interval: Option<u64>,
+        /// Stop after this many samples.
+        #[arg(short, long)]
+        count: Option<usize>,
+    },
+    /// Uploads new firmware from a .bin or .hex file. The device restarts once done.
+    Dfu { file: PathBuf },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Prints the whole config, or a single field.
+    Get {
+        #[arg(value_parser = config::parse_field)]
+        field: Option<ConfigField>,
+    },
+    /// Changes a single field, e.g. `name Balcony` or `calibration.0 900000:50`.
+    Set {
+        #[arg(value_parser = config::parse_field)]
+        field: ConfigField,
+        value: String,
+    },
+    /// Restores part of the config to its defaults: all, calibration or sampling-periods.
+    Reset {
+        #[arg(value_parser = config::parse_reset_scope)]
+        scope: ResetScope,
+    },
+}
+
+type SerialLink = Link<Box<dyn SerialPort>>;
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let port = serialport::new(&cli.port, cli.baudrate)
+        .timeout(Duration::from_millis(100))
+        .open()
+        .with_context(|| format!("failed to open {}", cli.port))?;
+    let mut link = Link::new(port).with_timeout(Duration::from_millis(cli.timeout_ms));
+
+    match cli.command {
+        Command::Info => info(&mut link),
+        Command::Version => version(&mut link),
+        Command::Mac => mac(&mut link),
+        Command::Config(command) => config(&mut link, command),
+        Command::Data {
+            stream: true,
+            count,
+            ..
+        } => stream(&mut link, count),
+        Command::Data {
+            interval, count, ..
+        } => poll(&mut link, interval.map(Duration::from_secs), count),
+        Command::Dfu { file } => upload(&mut link, &file),
+    }
+}
+
+fn info(link: &mut SerialLink) -> anyhow::Result<()> {
+    let ResponseTypeOk::DeviceInfo(info) = link.request(CommPacketType::GetDeviceInfo)? else {
+        bail!("unexpected response");
+    };
+
+    println!("protocol version:   {}", info.protocol_version);
+    println!("firmware version:   {}", info.firmware_version);
+    println!("build:              {}", info.build_hash);
+    match info.mac_address {
+        Some(address) => println!("MAC address:        {}", format_mac(address)),
+        None => println!("MAC address:        not initialized"),
+    }
+    println!("board:              {:?}", info.board);
+    match info.softdevice {
+        Some(sd) => println!(
+            "SoftDevice:         {:#06x} (LL version {}, company {:#06x})",
+            sd.subversion_number, sd.version_number, sd.company_id
+        ),
+        None => println!("SoftDevice:         disabled"),
+    }
+    println!("supported commands: {:#010x}", info.supported_commands);
+    println!("features:           {:#04x}", info.features);
+
+    Ok(())
+}
+
+fn version(link: &mut SerialLink) -> anyhow::Result<()> {
+    match link.request(CommPacketType::DfuPacket(DfuPayload::RequestFwVersion))? {
+        ResponseTypeOk::Dfu(DfuResponse::FirmwareVersion(version)) => println!("{version}"),
+        response => return Err(Error::Unexpected(Box::new(response)).into()),
+    }
+
+    Ok(())
+}
+
+/// BLE addresses are sent least significant byte first.
+fn format_mac(address: [u8; 6]) -> String {
+    let bytes: Vec<String> = address.iter().rev().map(|b| format!("{b:02X}")).collect();
+    bytes.join(":")
+}
+
+fn mac(link: &mut SerialLink) -> anyhow::Result<()> {
+    match link.request(CommPacketType::GetMacAddress)? {
+        ResponseTypeOk::MacAddress(address) => println!("{}", format_mac(address)),
+        response => return Err(Error::Unexpected(Box::new(response)).into()),
+    }
+
+    Ok(())
+}
+
+fn config(link: &mut SerialLink, command: ConfigCommand) -> anyhow::Result<()> {
+    let payload = match command {
+        ConfigCommand::Get { field: None } => ConfigPayload::ConfigGet,
+        ConfigCommand::Get { field: Some(field) } => ConfigPayload::ConfigGetField(field),
+        ConfigCommand::Set { field, value } => {
+            let value = config::parse_value(field, &value).map_err(anyhow::Error::msg)?;
+            ConfigPayload::ConfigPatch(value)
+        }
+        ConfigCommand::Reset { scope } => {
+            link.request(CommPacketType::FactoryReset(scope))?;
+            println!("ok");
+            return Ok(());
+        }
+    };
+
+    let ResponseTypeOk::Config(response) = link.request(CommPacketType::ConfigPacket(payload))?
+    else {
+        bail!("unexpected response");
+    };
+    match response {
+        ConfigResponse::GetConfig(config) => {
+            let period = &config.sampling_period;
+            println!("name:                   {}", config.name);
+            println!("onboard-sdt-plugged-ms: {}", period.onboard_sdt_plugged_ms);
+            println!("probe-sdt-plugged-ms:   {}", period.probe_sdt_plugged_ms);
+            println!("onboard-sdt-battery-ms: {}", period.onboard_sdt_battery_ms);
+            println!("probe-sdt-battery-ms:   {}", period.probe_sdt_battery_ms);
+            let calibration = config.probe_calibration.inner();
+            for (index, point) in calibration.chunks(2).enumerate() {
+                if let [frequency, fraction] = point {
+                    println!(
+                        "calibration.{index}:          {}:{}",
+                        *frequency as u32,
+                        (fraction * 100.0).round() as u8
+                    );
+                }
+            }
+        }
+        ConfigResponse::GetField(value) => println!("{}", DisplayValue(&value)),
+        _ => println!("ok"),
+    }
+
+    Ok(())
+}
+
+fn print_onboard(sample: &OnboardSample) {
+    let env = &sample.environment_data;
+    println!(
+        "onboard: {:.1} °C, {:.1} %RH, {:.1} lx, battery {:.2} V",
+        env.temperature, env.humidity, env.illuminance, sample.battery_level.value
+    );
+}
+
+fn print_probe(sample: &ProbeSample) {
+    println!(
+        "probe:   {:.1} % moisture ({:.0} Hz), {:.1} °C",
+        sample.moisture, sample.moisture_raw, sample.temperature
+    );
+}
+
+fn poll(
+    link: &mut SerialLink,
+    interval: Option<Duration>,
+    count: Option<usize>,
+) -> anyhow::Result<()> {
+    let mut polled = 0;
+    loop {
+        match link.request(CommPacketType::GetLatestSensordata)? {
+            ResponseTypeOk::SensorData(data) => {
+                print_onboard(&data.onboard);
+                print_probe(&data.probe);
+            }
+            response => return Err(Error::Unexpected(Box::new(response)).into()),
+        }
+
+        polled += 1;
+        match interval {
+            Some(interval) if count.is_none_or(|count| polled < count) => {
+                std::thread::sleep(interval)
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+fn stream(link: &mut SerialLink, count: Option<usize>) -> anyhow::Result<()> {
+    link.request(CommPacketType::SubscribeSensorData)?;
+
+    let mut received = 0;
+    while count.is_none_or(|count| received < count) {
+        let event = match link.next_event() {
+            Ok(event) => event,
+            // Samples only come every few seconds.
+            Err(Error::Timeout) => continue,
+            Err(err) => return Err(err.into()),
+        };
+        match event {
+            CommEvent::OnboardData(reading) => {
+                print!("raw      ");
+                print_onboard(&reading.raw);
+                print!("filtered ");
+                print_onboard(&reading.filtered);
+            }
+            CommEvent::ProbeData(reading) => {
+                print!("raw      ");
+                print_probe(&reading.raw);
+                print!("filtered ");
+                print_probe(&reading.filtered);
+            }
+            _ => continue,
+        }
+        received += 1;
+    }
+
+    link.request(CommPacketType::UnsubscribeSensorData)?;
+    Ok(())
+}
+
+fn upload(link: &mut SerialLink, file: &Path) -> anyhow::Result<()> {
+    let image = dfu::load_image(file)?;
+    println!("Uploading {} bytes from {}", image.len(), file.display());
+
+    dfu::upload(link, &image, |sent, total| {
+        if sent % 64 == 0 || sent == total {
+            println!("{sent}/{total} blocks");
+        }
+    })?;
+    println!("Done. The device restarts into the new firmware.");
+
+    Ok(())
+}
@@ -0,0 +1,259 @@
+//! Runs the host side against a fake device on the other end of a pseudo-terminal.
+
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use heapless::String;
+use sensus_protocol::comm::{
+    BoardVariant, CommEvent, CommMessage, CommPacket, CommPacketType, DeviceInfo, ResponseTypeErr,
+    ResponseTypeOk, PROTOCOL_VERSION,
+};
+use sensus_protocol::config::{
+    ConfigField, ConfigPayload, ConfigResponse, ConfigSections, ConfigValue, DisplayableVec,
+    SamplePeriod, SensusConfigOld, MAX_CALIBRATION_POINTS,
+};
+use sensus_protocol::dfu::{DfuPayload, DfuResponse};
+use sensus_protocol::fragment::{self, MAX_MESSAGE_SIZE};
+use sensus_protocol::framing;
+use serialport::{SerialPort, TTYPort};
+
+use sensus_cli::dfu;
+use sensus_cli::link::{Error, Link};
+
+/// Reads frames off the port until it gets a complete packet.
+fn read_packet(port: &mut TTYPort, rx_buf: &mut Vec<u8>) -> Option<CommPacket> {
+    let mut chunk = [0u8; 256];
+    loop {
+        if let Some(end) = rx_buf.iter().position(|&b| b == 0x00) {
+            let rest = rx_buf.split_off(end + 1);
+            let mut frame = std::mem::replace(rx_buf, rest);
+            return Some(framing::decode(&mut frame).unwrap());
+        }
+        match port.read(&mut chunk) {
+            Ok(n) => rx_buf.extend_from_slice(&chunk[..n]),
+            Err(err) if err.kind() == std::io::ErrorKind::TimedOut => {}
+            // The host hung up.
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Sends a message in fragments of 64 bytes, like the firmware does over UART.
+fn write_message(port: &mut TTYPort, message: &CommMessage) {
+    static NUMBER: AtomicU8 = AtomicU8::new(0);
+
+    let mut message_buf = [0u8; MAX_MESSAGE_SIZE];
+    let number = NUMBER.fetch_add(1, Ordering::Relaxed);
+    for fragment in fragment::split(message, number, 64, &mut message_buf).unwrap() {
+        let mut buf = [0u8; 128];
+        port.write_all(framing::encode(&fragment, &mut buf).unwrap())
+            .unwrap();
+    }
+}
+
+/// A config that doesn't fit into a single fragment.
+fn full_config(name: &str) -> SensusConfigOld {
+    let mut calibration = heapless::Vec::new();
+    for point in 0..MAX_CALIBRATION_POINTS {
+        calibration.push(100000.0 * point as f32).unwrap();
+        calibration.push(0.1 * point as f32).unwrap();
+    }
+    SensusConfigOld {
+        sampling_period: SamplePeriod::default(),
+        name: String::from(name),
+        probe_calibration: DisplayableVec(calibration),
+    }
+}
+
+/// Answers requests the way the firmware does. Returns the uploaded firmware image, if any.
+fn fake_device(mut port: TTYPort) -> Vec<u8> {
+    let mut rx_buf = Vec::new();
+    let mut image = Vec::new();
+    let mut blocks_left = 0u16;
+    let mut name: String<29> = String::from("Sensus");
+
+    while let Some(packet) = read_packet(&mut port, &mut rx_buf) {
+        if let Some(message_id) = packet.payload.unsupported() {
+            write_message(
+                &mut port,
+                &CommMessage::err(packet.id, ResponseTypeErr::UnsupportedCommand(message_id)),
+            );
+            continue;
+        }
+        let response = match packet.payload {
+            CommPacketType::GetDeviceInfo => ResponseTypeOk::DeviceInfo(DeviceInfo {
+                protocol_version: PROTOCOL_VERSION,
+                firmware_version: String::from("v0.0.2"),
+                build_hash: String::from("0123abcd"),
+                mac_address: Some([6, 5, 4, 3, 2, 1]),
+                supported_commands: CommPacketType::SUPPORTED_COMMANDS,
+                features: 0,
+                board: BoardVariant::Nrf52832,
+                softdevice: None,
+            }),
+            CommPacketType::ConfigPacket(ConfigPayload::ConfigPatch(ConfigValue::Name(value))) => {
+                name = value;
+                // Config changes are announced before the response.
+                write_message(
+                    &mut port,
+                    &CommMessage::Event(CommEvent::ConfigChanged(ConfigSections::NAME)),
+                );
+                ResponseTypeOk::Config(ConfigResponse::PatchConfig(ConfigField::Name))
+            }
+            CommPacketType::ConfigPacket(ConfigPayload::ConfigGetField(ConfigField::Name)) => {
+                ResponseTypeOk::Config(ConfigResponse::GetField(ConfigValue::Name(name.clone())))
+            }
+            CommPacketType::ConfigPacket(ConfigPayload::ConfigGet) => {
+                ResponseTypeOk::Config(ConfigResponse::GetConfig(full_config(&name)))
+            }
+            CommPacketType::DfuPacket(DfuPayload::StartDfu(header)) => {
+                image.clear();
+                blocks_left = header.no_blocks;
+                ResponseTypeOk::Dfu(DfuResponse::RequestBlock(0u16.to_le_bytes()))
+            }
+            CommPacketType::DfuPacket(DfuPayload::Block(block)) => {
+                image.extend_from_slice(&block.data);
+                blocks_left -= 1;
+                match blocks_left {
+                    0 => ResponseTypeOk::Dfu(DfuResponse::DfuDone),
+                    _ => ResponseTypeOk::Dfu(DfuResponse::RequestBlock(
+                        (block.block_idx + 1).to_le_bytes(),
+                    )),
+                }
+            }
+            CommPacketType::GetMacAddress => {
+                // As if BLE wasn't up yet.
+                write_message(
+                    &mut port,
+                    &CommMessage::err(packet.id, ResponseTypeErr::MacAddressNotInitialized),
+                );
+                continue;
+            }
+            payload => panic!("unexpected request {payload:?}"),
+        };
+        write_message(&mut port, &CommMessage::ok(packet.id, response));
+    }
+
+    image
+}
+
+fn connect() -> (Link<TTYPort>, thread::JoinHandle<Vec<u8>>) {
+    let (mut host, mut device) = TTYPort::pair().expect("failed to create a pseudo-terminal");
+    host.set_timeout(Duration::from_millis(10)).unwrap();
+    device.set_timeout(Duration::from_millis(10)).unwrap();
+
+    let device = thread::spawn(move || fake_device(device));
+    (Link::new(host), device)
+}
+
+#[test]
+fn device_info() {
+    let (mut link, _device) = connect();
+
+    match link.request(CommPacketType::GetDeviceInfo).unwrap() {
+        ResponseTypeOk::DeviceInfo(info) => {
+            assert_eq!(info.protocol_version, PROTOCOL_VERSION);
+            assert_eq!(info.firmware_version, "v0.0.2");
+            assert_eq!(info.mac_address, Some([6, 5, 4, 3, 2, 1]));
+        }
+        response => panic!("unexpected response {response:?}"),
+    }
+}
+
+#[test]
+fn config_patch_keeps_events() {
+    let (mut link, _device) = connect();
+
+    let name = ConfigValue::Name(String::from("Balcony"));
+    let response = link
+        .request(CommPacketType::ConfigPacket(ConfigPayload::ConfigPatch(
+            name.clone(),
+        )))
+        .unwrap();
+    assert_eq!(
+        response,
+        ResponseTypeOk::Config(ConfigResponse::PatchConfig(ConfigField::Name))
+    );
+
+    let response = link
+        .request(CommPacketType::ConfigPacket(ConfigPayload::ConfigGetField(
+            ConfigField::Name,
+        )))
+        .unwrap();
+    assert_eq!(
+        response,
+        ResponseTypeOk::Config(ConfigResponse::GetField(name))
+    );
+
+    // The event sent before the first response is still there.
+    assert!(matches!(
+        link.next_event(),
+        Ok(CommEvent::ConfigChanged(ConfigSections::NAME))
+    ));
+}
+
+#[test]
+fn large_responses_are_reassembled() {
+    let (mut link, _device) = connect();
+
+    let response = link
+        .request(CommPacketType::ConfigPacket(ConfigPayload::ConfigGet))
+        .unwrap();
+    assert_eq!(
+        response,
+        ResponseTypeOk::Config(ConfigResponse::GetConfig(full_config("Sensus")))
+    );
+}
+
+#[test]
+fn device_errors_are_reported() {
+    let (mut link, _device) = connect();
+
+    let result = link.request(CommPacketType::GetMacAddress);
+    assert!(matches!(
+        result,
+        Err(Error::Device(ResponseTypeErr::MacAddressNotInitialized))
+    ));
+}
+
+#[test]
+fn unsupported_commands_are_reported() {
+    let (mut link, _device) = connect();
+
+    // As sent by a host that is newer than the firmware.
+    let result = link.request(CommPacketType::ConfigPacket(ConfigPayload::Unsupported(
+        0x2F,
+    )));
+    assert!(matches!(
+        result,
+        Err(Error::Device(ResponseTypeErr::UnsupportedCommand(0x2F)))
+    ));
+
+    // The link is still usable afterwards.
+    assert!(link.request(CommPacketType::GetDeviceInfo).is_ok());
+}
+
+#[test]
+fn dfu_upload() {
+    let (mut link, device) = connect();
+
+    let hex = ":020000040002F8\n\
+               :0400000001020304F2\n\
+               :0400080005060708DA\n\
+               :00000001FF\n";
+    let mut image = dfu::image_from_hex(hex).unwrap();
+    assert_eq!(image, [1, 2, 3, 4, 0xFF, 0xFF, 0xFF, 0xFF, 5, 6, 7, 8]);
+    image.resize(dfu::PAGE_SIZE, 0xFF);
+
+    let mut last_progress = (0, 0);
+    dfu::upload(&mut link, &image, |sent, total| {
+        last_progress = (sent, total)
+    })
+    .unwrap();
+    assert_eq!(last_progress, (64, 64));
+
+    drop(link);
+    assert_eq!(device.join().unwrap(), image);
+}
@@ -0,0 +1,216 @@
+use heapless::String;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ConfigError, ConfigPayload, ConfigResponse, ConfigSections, ResetScope};
+use crate::dfu::{DfuError, DfuPayload, DfuResponse};
+use crate::sensors::{OnboardReading, ProbeReading, SensorDataRaw};
+pub use crate::tagged::MessageId;
+
+/// Version of the host protocol. Bump whenever the wire format of `CommPacket` or `CommMessage`
+/// changes in a way older hosts can't handle.
+pub const PROTOCOL_VERSION: u16 = 4;
+
+/// Chosen by the host for every request and echoed back in the matching response, so the host can
+/// pair them up, pipeline requests and notice when a reply got lost.
+pub type RequestId = u16;
+
+/// Everything we send to the host.
+///
+/// Encoded by variant index like the value types, so this order is frozen.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum CommMessage {
+    /// Answers the request with the same ID.
+    Response(
+        #[serde(with = "postcard::fixint::le")] RequestId,
+        CommResponse,
+    ),
+    /// Sent on our own initiative, not as an answer to any request.
+    Event(CommEvent),
+}
+
+tagged_enum! {
+    /// Unsolicited notifications. Hosts should ignore the ones they don't know.
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum CommEvent {
+        /// A packet could not be decoded, so we don't know which request it was. The host should
+        /// treat all outstanding requests sent over the same link as possibly lost.
+        PacketError(PacketError) = 0x30,
+        /// The given config sections were changed by a request.
+        ConfigChanged(ConfigSections) = 0x31,
+        /// New onboard sample. Only sent while subscribed to the sensor data stream.
+        OnboardData(OnboardReading) = 0x32,
+        /// New probe sample. Only sent while subscribed to the sensor data stream.
+        ProbeData(ProbeReading) = 0x33,
+        _ => Unknown,
+    }
+}
+
+/// Encoded by variant index, so this order is frozen.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum CommResponse {
+    Ok(ResponseTypeOk),
+    Err(ResponseTypeErr),
+}
+
+tagged_enum! {
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum ResponseTypeOk {
+        Dfu(DfuResponse) = 0x40,
+        Config(ConfigResponse) = 0x41, // Returns either a config or just an OK if we stored the config
+        SensorData(SensorDataRaw) = 0x42,
+        MacAddress([u8; 6]) = 0x43,
+        FactoryReset(ResetScope) = 0x44, // Confirms the config was reset to its defaults.
+        DeviceInfo(DeviceInfo) = 0x45,
+        SensorStreamSubscribed = 0x46,
+        SensorStreamUnsubscribed = 0x47,
+    }
+}
+
+tagged_enum! {
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum ResponseTypeErr {
+        Phys(PacketError) = 0x50,
+        Dfu(DfuError) = 0x51,
+        Config(ConfigError) = 0x52,
+        MacAddressNotInitialized = 0x53,
+        FailedToGetSensorData = 0x54,
+        /// The request with the given message ID isn't supported by this firmware.
+        UnsupportedCommand(MessageId) = 0x55,
+        /// The response could not be encoded, e.g. because it is larger than
+        /// `fragment::MAX_MESSAGE_SIZE`.
+        EncodingFailed = 0x56,
+        /// The request would change the firmware or the stored config, and came in over a BLE link
+        /// that isn't encrypted. Pair first.
+        InsecureLink = 0x57,
+    }
+}
+
+/// Everything a host needs to know in order to adapt to the firmware it's talking to.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeviceInfo {
+    #[serde(with = "postcard::fixint::le")]
+    pub protocol_version: u16,
+    #[cfg_attr(feature = "defmt", defmt(Display2Format))]
+    pub firmware_version: String<16>,
+    /// Short hash of the commit the firmware was built from.
+    #[cfg_attr(feature = "defmt", defmt(Display2Format))]
+    pub build_hash: String<16>,
+    /// `None` if BLE isn't up yet.
+    pub mac_address: Option<[u8; 6]>,
+    /// Bit N is set if the `CommPacketType` with message ID N is supported.
+    #[serde(with = "postcard::fixint::le")]
+    pub supported_commands: u32,
+    /// Optional firmware features that were compiled in. See `FEATURE_*`.
+    pub features: u8,
+    pub board: BoardVariant,
+    /// `None` if the SoftDevice isn't enabled yet.
+    pub softdevice: Option<SoftdeviceVersion>,
+}
+
+pub const FEATURE_BLE_GATT_SERVER: u8 = 1 << 0;
+pub const FEATURE_BLE_GATT_CLIENT: u8 = 1 << 1;
+pub const FEATURE_BLE_SEC: u8 = 1 << 2;
+pub const FEATURE_BLE_L2CAP: u8 = 1 << 3;
+pub const FEATURE_EXTENDED_ADVERTISING: u8 = 1 << 4;
+/// Firmware updates and config changes are taken over BLE links that aren't encrypted, too.
+pub const FEATURE_BLE_DFU: u8 = 1 << 5;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum BoardVariant {
+    Nrf52832,
+}
+
+/// As reported by `sd_ble_version_get`.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SoftdeviceVersion {
+    /// Link Layer version number.
+    pub version_number: u8,
+    #[serde(with = "postcard::fixint::le")]
+    pub company_id: u16,
+    /// Identifies the SoftDevice build, e.g. 0x0124 for S132 v7.3.0.
+    #[serde(with = "postcard::fixint::le")]
+    pub subversion_number: u16,
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum PacketError {
+    /// Error with the physical reception of bytes. For example due to noise on UART.
+    PhysError,
+    /// General decoding error when trying to create a packet from raw bytes.
+    DeserializationError,
+    PacketCRC,
+    /// A frame wasn't completed within the serial timeouts. Its bytes were discarded.
+    Timeout,
+}
+
+/// A request from the host. The CRC is added and checked by the `framing` layer.
+#[repr(C)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CommPacket {
+    #[serde(with = "postcard::fixint::le")]
+    pub id: RequestId,
+    pub payload: CommPacketType,
+}
+
+tagged_enum! {
+    #[repr(C)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum CommPacketType {
+        DfuPacket(DfuPayload) = 0x00,
+        ConfigPacket(ConfigPayload) = 0x01,
+        GetLatestSensordata = 0x02,
+        GetMacAddress = 0x03,
+        FactoryReset(ResetScope) = 0x04,
+        GetDeviceInfo = 0x05,
+        /// Starts pushing every new sample as a `CommEvent`.
+        SubscribeSensorData = 0x06,
+        UnsubscribeSensorData = 0x07,
+        _ => Unsupported,
+    }
+}
+
+impl CommPacketType {
+    /// Bit N is set if the command with message ID N is supported, see
+    /// `DeviceInfo::supported_commands`. Command IDs past 31 won't fit and fail to compile.
+    pub const SUPPORTED_COMMANDS: u32 = {
+        let mut mask = 0;
+        let mut i = 0;
+        while i < Self::IDS.len() {
+            assert!(Self::IDS[i] < 32, "command ID doesn't fit the supported commands mask");
+            mask |= 1 << Self::IDS[i];
+            i += 1;
+        }
+        mask
+    };
+
+    /// Message ID of the unknown request, if this or the payload it carries is one.
+    pub fn unsupported(&self) -> Option<MessageId> {
+        match self {
+            CommPacketType::Unsupported(id)
+            | CommPacketType::DfuPacket(DfuPayload::Unsupported(id))
+            | CommPacketType::ConfigPacket(ConfigPayload::Unsupported(id)) => Some(*id),
+            _ => None,
+        }
+    }
+}
+
+impl CommMessage {
+    pub fn ok(id: RequestId, response: ResponseTypeOk) -> Self {
+        Self::Response(id, CommResponse::Ok(response))
+    }
+
+    pub fn err(id: RequestId, error: ResponseTypeErr) -> Self {
+        Self::Response(id, CommResponse::Err(error))
+    }
+}
@@ -0,0 +1,261 @@
+use core::ops::BitOr;
+
+use heapless::{String, Vec};
+use serde::{Deserialize, Serialize};
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    SerializationError,
+    InvalidSampleRate,
+    Flash(u8),
+    /// The stored record has a bad magic, length or CRC.
+    CorruptRecord,
+    /// The stored record has a schema version this firmware doesn't know how to read.
+    UnknownVersion(u16),
+    /// The advertised name is empty.
+    InvalidName,
+    /// There is no calibration point with the given index.
+    CalibrationIndexOutOfRange(u8),
+    /// Calibration percentages have to lie between 0 and 100.
+    CalibrationPercentageOutOfRange,
+    /// Advertising intervals have to lie between 20ms and 10.24s.
+    InvalidAdvInterval,
+    /// The radio doesn't support the requested TX power.
+    InvalidTxPower,
+    /// A calibration needs at least two points.
+    CalibrationTooFewPoints,
+    /// There is no room for another calibration point.
+    CalibrationFull,
+    /// Calibration frequencies have to be unique and map to strictly increasing or strictly
+    /// decreasing moisture percentages.
+    CalibrationNotMonotonic,
+    /// The frame timeout has to be at least 10ms and the inter-byte timeout at least 3ms, without
+    /// exceeding the frame timeout.
+    InvalidSerialTimeout,
+    /// Filter settling times have to be either 0, to disable filtering, or between 1s and a week.
+    InvalidSettlingTime,
+    /// A flat calibration list has an odd number of values, or a frequency that isn't a finite,
+    /// non-negative number fitting 32 bits.
+    CalibrationMalformed,
+}
+
+/// How often the sensors are sampled, in milliseconds. Every period has to lie between 1s and a
+/// day, otherwise the config is rejected with `ConfigError::InvalidSampleRate`.
+#[repr(C)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SamplePeriod {
+    #[serde(with = "postcard::fixint::le")]
+    pub onboard_sdt_plugged_ms: u32,
+    #[serde(with = "postcard::fixint::le")]
+    pub probe_sdt_plugged_ms: u32,
+    #[serde(with = "postcard::fixint::le")]
+    pub onboard_sdt_battery_ms: u32,
+    #[serde(with = "postcard::fixint::le")]
+    pub probe_sdt_battery_ms: u32,
+}
+
+impl Default for SamplePeriod {
+    fn default() -> Self {
+        Self {
+            onboard_sdt_plugged_ms: 10000,
+            probe_sdt_plugged_ms: 10000,
+            onboard_sdt_battery_ms: 30000,
+            probe_sdt_battery_ms: 30000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DisplayableVec<T, const N: usize>(pub Vec<T, N>);
+
+impl<T, const N: usize> DisplayableVec<T, N> {
+    pub fn inner(self) -> Vec<T, N> {
+        self.0
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<T, const N: usize> defmt::Format for DisplayableVec<T, N>
+where
+    T: defmt::Format,
+{
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{}", self.0.as_slice())
+    }
+}
+
+/// Maximum number of calibration points a device can store.
+pub const MAX_CALIBRATION_POINTS: usize = 10;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
+pub struct CalibrationPoint {
+    pub frequency: u32,
+    pub percentage: u8,
+}
+
+/// Set of config sections, used to tell which parts of the config changed.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct ConfigSections(u8);
+
+impl ConfigSections {
+    pub const NONE: Self = Self(0);
+    pub const SAMPLING_PERIOD: Self = Self(1 << 0);
+    pub const NAME: Self = Self(1 << 1);
+    pub const CALIBRATION: Self = Self(1 << 2);
+    pub const SENSORS: Self = Self(1 << 3);
+    pub const ADVERTISING: Self = Self(1 << 4);
+    pub const FILTER: Self = Self(1 << 5);
+    pub const SERIAL: Self = Self(1 << 6);
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// True if any of the sections in `other` is part of this set.
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl BitOr for ConfigSections {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Sensors that can be switched off individually, for example when no probe is attached.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Sensor {
+    Probe,
+    Opt3001,
+    Shtc3,
+    Battery,
+}
+
+/// The config as exchanged in full with `ConfigPayload::ConfigSet` and
+/// `ConfigResponse::GetConfig`.
+#[repr(C)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SensusConfigOld {
+    pub sampling_period: SamplePeriod,
+    // TODO. Remove and replace with Format implementation
+    #[cfg_attr(feature = "defmt", defmt(Display2Format))]
+    pub name: String<29>,
+    /// Flat `[f0, p0, f1, p1, ...]` list, with percentages given as fractions between 0 and 1.
+    pub probe_calibration: DisplayableVec<f32, 20>,
+}
+
+/// Addresses a single field of the config.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ConfigField {
+    OnboardSdtPluggedMs,
+    ProbeSdtPluggedMs,
+    OnboardSdtBatteryMs,
+    ProbeSdtBatteryMs,
+    Name,
+    /// Calibration point with the given index, sorted by frequency.
+    CalibrationPoint(u8),
+    SensorEnabled(Sensor),
+    AdvIntervalPluggedMs,
+    AdvIntervalBatteryMs,
+    TxPowerPluggedDbm,
+    TxPowerBatteryDbm,
+    EnvironmentSettlingMs,
+    BatterySettlingMs,
+    ProbeSettlingMs,
+    SerialFrameTimeoutMs,
+    SerialByteTimeoutMs,
+}
+
+/// The value of a single field of the config.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ConfigValue {
+    OnboardSdtPluggedMs(u32),
+    ProbeSdtPluggedMs(u32),
+    OnboardSdtBatteryMs(u32),
+    ProbeSdtBatteryMs(u32),
+    Name(#[cfg_attr(feature = "defmt", defmt(Display2Format))] String<29>),
+    CalibrationPoint(u8, CalibrationPoint),
+    SensorEnabled(Sensor, bool),
+    AdvIntervalPluggedMs(u32),
+    AdvIntervalBatteryMs(u32),
+    TxPowerPluggedDbm(i8),
+    TxPowerBatteryDbm(i8),
+    EnvironmentSettlingMs(u32),
+    BatterySettlingMs(u32),
+    ProbeSettlingMs(u32),
+    SerialFrameTimeoutMs(u32),
+    SerialByteTimeoutMs(u32),
+}
+
+impl ConfigValue {
+    /// Returns the field this value belongs to.
+    pub fn field(&self) -> ConfigField {
+        match self {
+            ConfigValue::OnboardSdtPluggedMs(_) => ConfigField::OnboardSdtPluggedMs,
+            ConfigValue::ProbeSdtPluggedMs(_) => ConfigField::ProbeSdtPluggedMs,
+            ConfigValue::OnboardSdtBatteryMs(_) => ConfigField::OnboardSdtBatteryMs,
+            ConfigValue::ProbeSdtBatteryMs(_) => ConfigField::ProbeSdtBatteryMs,
+            ConfigValue::Name(_) => ConfigField::Name,
+            ConfigValue::CalibrationPoint(index, _) => ConfigField::CalibrationPoint(*index),
+            ConfigValue::SensorEnabled(sensor, _) => ConfigField::SensorEnabled(*sensor),
+            ConfigValue::AdvIntervalPluggedMs(_) => ConfigField::AdvIntervalPluggedMs,
+            ConfigValue::AdvIntervalBatteryMs(_) => ConfigField::AdvIntervalBatteryMs,
+            ConfigValue::TxPowerPluggedDbm(_) => ConfigField::TxPowerPluggedDbm,
+            ConfigValue::TxPowerBatteryDbm(_) => ConfigField::TxPowerBatteryDbm,
+            ConfigValue::EnvironmentSettlingMs(_) => ConfigField::EnvironmentSettlingMs,
+            ConfigValue::BatterySettlingMs(_) => ConfigField::BatterySettlingMs,
+            ConfigValue::ProbeSettlingMs(_) => ConfigField::ProbeSettlingMs,
+            ConfigValue::SerialFrameTimeoutMs(_) => ConfigField::SerialFrameTimeoutMs,
+            ConfigValue::SerialByteTimeoutMs(_) => ConfigField::SerialByteTimeoutMs,
+        }
+    }
+}
+
+/// Selects which part of the config a factory reset restores.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ResetScope {
+    All,
+    Calibration,
+    SamplingPeriods,
+}
+
+tagged_enum! {
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum ConfigPayload {
+        ConfigGet = 0x20,
+        ConfigSet(SensusConfigOld) = 0x21,
+        ConfigGetField(ConfigField) = 0x22,
+        ConfigPatch(ConfigValue) = 0x23,
+        CalibrationList = 0x24,
+        CalibrationAdd(CalibrationPoint) = 0x25,
+        CalibrationRemove(u8) = 0x26,
+        _ => Unsupported,
+    }
+}
+
+tagged_enum! {
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum ConfigResponse {
+        GetConfig(SensusConfigOld) = 0x70,
+        SetConfig = 0x71, // Set config successfully.
+        GetField(ConfigValue) = 0x72,
+        PatchConfig(ConfigField) = 0x73, // Patched the given field successfully.
+        CalibrationPoints(DisplayableVec<CalibrationPoint, MAX_CALIBRATION_POINTS>) = 0x74,
+        CalibrationAdded(u8) = 0x75, // Index the new point was inserted at.
+        CalibrationRemoved(u8) = 0x76,
+    }
+}
@@ -0,0 +1,59 @@
+use heapless::{String, Vec};
+use serde::{Deserialize, Serialize};
+
+/// The size of each DFU transaction
+pub const BLOCK_SIZE: usize = 64;
+/// PSM of the L2CAP channel firmware images can be streamed over, from the dynamic LE range.
+pub const L2CAP_DFU_PSM: u16 = 0x0081;
+
+tagged_enum! {
+    #[repr(C)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum DfuPayload {
+        StartDfu(DfuHeader) = 0x10,
+        Block(DfuBlock) = 0x11,
+        RequestFwVersion = 0x12,
+        _ => Unsupported,
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DfuHeader {
+    #[serde(with = "postcard::fixint::le")]
+    pub binary_size: u32,
+    #[serde(with = "postcard::fixint::le")]
+    pub no_blocks: u16,
+}
+
+#[repr(C)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DfuBlock {
+    #[serde(with = "postcard::fixint::le")]
+    pub block_idx: u16,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub data: Vec<u8, BLOCK_SIZE>,
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DfuError {
+    StateMachineError,
+    TimeoutError,
+    /// The image is empty, doesn't fit the flash or doesn't match its number of blocks.
+    InvalidImageSize,
+    /// Writing the image to flash failed.
+    FlashError,
+}
+
+tagged_enum! {
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum DfuResponse {
+        DfuDone = 0x60,
+        RequestBlock([u8; 2]) = 0x61,
+        FirmwareVersion(#[cfg_attr(feature = "defmt", defmt(Display2Format))] String<16>) = 0x62,
+    }
+}
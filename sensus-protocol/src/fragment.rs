@@ -0,0 +1,238 @@
+//! Splits messages that don't fit into a single frame, and puts them back together.
+//!
+//! Every message from the device is sent as one or more `Fragment`s, each in a frame of its own. A
+//! message that fits into one frame is a single fragment with a `total` of 1. Fragments of a
+//! message are always sent back to back, in order.
+
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::framing::{serialization_error, FramingError};
+
+/// Largest encoded message. Hosts need a reassembly buffer this large.
+pub const MAX_MESSAGE_SIZE: usize = 512;
+
+/// Bytes a fragment adds on top of its data: the message number, index, total and the length of
+/// the data, which takes 2 bytes from 128 bytes on.
+pub const FRAGMENT_OVERHEAD: usize = 5;
+
+/// Part of an encoded message.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Fragment<'a> {
+    /// Same for all fragments of a message, and incremented for every message. Wraps around.
+    pub message: u8,
+    /// Position of this fragment, starting at 0.
+    pub index: u8,
+    /// Number of fragments the message was split into.
+    pub total: u8,
+    pub data: &'a [u8],
+}
+
+/// The fragments of a message, in the order they must be sent.
+#[derive(Debug, Clone)]
+pub struct Fragments<'a> {
+    message: u8,
+    total: u8,
+    chunks: core::iter::Enumerate<core::slice::Chunks<'a, u8>>,
+}
+
+impl<'a> Iterator for Fragments<'a> {
+    type Item = Fragment<'a>;
+
+    fn next(&mut self) -> Option<Fragment<'a>> {
+        self.chunks.next().map(|(index, data)| Fragment {
+            message: self.message,
+            index: index as u8,
+            total: self.total,
+            data,
+        })
+    }
+}
+
+/// Encodes `value` into `buf` and splits it into fragments of at most `fragment_size` bytes of
+/// data, numbered as `message`.
+pub fn split<'a, T: Serialize + ?Sized>(
+    value: &T,
+    message: u8,
+    fragment_size: usize,
+    buf: &'a mut [u8],
+) -> Result<Fragments<'a>, FramingError> {
+    let encoded = postcard::to_slice(value, buf).map_err(serialization_error)?;
+    let chunks = encoded.chunks(fragment_size);
+    // Only happens with tiny fragments, in which case it's the buffer that's too big.
+    let total = u8::try_from(chunks.len()).map_err(|_| FramingError::BufferFull)?;
+
+    Ok(Fragments {
+        message,
+        total,
+        chunks: chunks.enumerate(),
+    })
+}
+
+/// Decodes a message put back together by a `Reassembler`.
+pub fn decode<'a, T: Deserialize<'a>>(message: &'a [u8]) -> Result<T, FramingError> {
+    postcard::from_bytes(message).map_err(|_| FramingError::Deserialization)
+}
+
+/// Puts fragmented messages back together.
+#[derive(Debug, Default)]
+pub struct Reassembler<const N: usize = MAX_MESSAGE_SIZE> {
+    buf: Vec<u8, N>,
+    /// Message number and index of the fragment expected next, while in the middle of a message.
+    next: Option<(u8, u8)>,
+}
+
+impl<const N: usize> Reassembler<N> {
+    pub fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            next: None,
+        }
+    }
+
+    /// Adds a fragment. Returns the encoded message once its last fragment is in.
+    ///
+    /// The first fragment of a message always starts over, so a message missing its tail is
+    /// dropped silently. Any other fragment that doesn't continue the current message drops it
+    /// with `FramingError::Fragment`.
+    pub fn push(&mut self, fragment: Fragment) -> Result<Option<&[u8]>, FramingError> {
+        if fragment.index == 0 {
+            self.buf.clear();
+            self.next = Some((fragment.message, 0));
+        }
+
+        if self.next != Some((fragment.message, fragment.index)) || fragment.index >= fragment.total
+        {
+            self.next = None;
+            return Err(FramingError::Fragment);
+        }
+        if self.buf.extend_from_slice(fragment.data).is_err() {
+            self.next = None;
+            return Err(FramingError::BufferFull);
+        }
+
+        if fragment.index + 1 == fragment.total {
+            self.next = None;
+            Ok(Some(&self.buf))
+        } else {
+            self.next = Some((fragment.message, fragment.index + 1));
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comm::{CommMessage, ResponseTypeOk};
+    use crate::config::{
+        ConfigResponse, DisplayableVec, SamplePeriod, SensusConfigOld, MAX_CALIBRATION_POINTS,
+    };
+    use crate::framing;
+
+    /// The largest config there is: a full calibration and the longest name.
+    fn large_message() -> CommMessage {
+        let mut calibration = Vec::new();
+        for point in 0..MAX_CALIBRATION_POINTS {
+            calibration.push(100000.0 * point as f32).unwrap();
+            calibration.push(1.0 / (point + 1) as f32).unwrap();
+        }
+        let config = SensusConfigOld {
+            sampling_period: SamplePeriod::default(),
+            name: heapless::String::from("A name that uses all 29 bytes"),
+            probe_calibration: DisplayableVec(calibration),
+        };
+        CommMessage::ok(7, ResponseTypeOk::Config(ConfigResponse::GetConfig(config)))
+    }
+
+    #[test]
+    fn messages_survive_fragmentation() {
+        let message = large_message();
+        let mut buf = [0u8; MAX_MESSAGE_SIZE];
+        let fragments = split(&message, 3, 64, &mut buf).unwrap();
+        assert!(fragments.clone().count() > 1);
+
+        let mut reassembler: Reassembler = Reassembler::new();
+        let mut result = None;
+        for fragment in fragments {
+            assert_eq!(fragment.message, 3);
+            assert!(fragment.data.len() <= 64);
+            assert!(result.is_none());
+
+            // Through a frame and back, as on the wire.
+            let mut frame_buf = [0u8; framing::max_frame_size(64 + FRAGMENT_OVERHEAD)];
+            let frame = framing::encode(&fragment, &mut frame_buf).unwrap();
+            let fragment: Fragment = framing::decode(frame).unwrap();
+            result = reassembler
+                .push(fragment)
+                .unwrap()
+                .map(|bytes| decode(bytes).unwrap());
+        }
+        assert_eq!(result, Some(message));
+    }
+
+    #[test]
+    fn small_messages_are_a_single_fragment() {
+        let message = CommMessage::ok(1, ResponseTypeOk::SensorStreamSubscribed);
+        let mut buf = [0u8; MAX_MESSAGE_SIZE];
+        let mut fragments = split(&message, 0, 64, &mut buf).unwrap();
+
+        let fragment = fragments.next().unwrap();
+        assert_eq!((fragment.index, fragment.total), (0, 1));
+        assert!(fragments.next().is_none());
+    }
+
+    #[test]
+    fn lost_fragments_are_detected() {
+        let mut buf = [0u8; MAX_MESSAGE_SIZE];
+        let fragments: std::vec::Vec<_> =
+            split(&large_message(), 0, 32, &mut buf).unwrap().collect();
+        let mut reassembler: Reassembler = Reassembler::new();
+
+        reassembler.push(fragments[0]).unwrap();
+        assert_eq!(reassembler.push(fragments[2]), Err(FramingError::Fragment));
+        // Nothing but a new message is accepted afterwards.
+        assert_eq!(reassembler.push(fragments[3]), Err(FramingError::Fragment));
+
+        // Starting over works, even when the previous message was cut short.
+        reassembler.push(fragments[0]).unwrap();
+        let mut result = None;
+        for fragment in &fragments {
+            result = reassembler
+                .push(*fragment)
+                .unwrap()
+                .map(|bytes| bytes.len());
+        }
+        assert!(result.is_some());
+    }
+
+    #[test]
+    fn fragments_of_other_messages_are_rejected() {
+        let mut buf = [0u8; MAX_MESSAGE_SIZE];
+        let mut fragments = split(&large_message(), 5, 32, &mut buf).unwrap();
+        let mut reassembler: Reassembler = Reassembler::new();
+
+        reassembler.push(fragments.next().unwrap()).unwrap();
+        let mut second = fragments.next().unwrap();
+        second.message = 6;
+        assert_eq!(reassembler.push(second), Err(FramingError::Fragment));
+    }
+
+    #[test]
+    fn oversized_messages_are_reported() {
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            split(&large_message(), 0, 8, &mut buf).err(),
+            Some(FramingError::BufferFull)
+        );
+
+        let mut buf = [0u8; MAX_MESSAGE_SIZE];
+        let fragments = split(&large_message(), 0, 32, &mut buf).unwrap();
+        let mut reassembler: Reassembler<32> = Reassembler::new();
+        let results: std::vec::Vec<_> = fragments
+            .map(|fragment| reassembler.push(fragment).map(|_| ()))
+            .collect();
+        assert_eq!(results[1], Err(FramingError::BufferFull));
+    }
+}
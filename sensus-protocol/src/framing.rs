@@ -0,0 +1,276 @@
+//! Framing shared by every transport.
+//!
+//! A frame is the postcard-encoded body followed by the `CRC_GSM` checksum of that body as a
+//! little-endian u16, all of it COBS-encoded and terminated by a 0x00.
+
+use crc::{Crc, Digest, CRC_16_GSM};
+use postcard::ser_flavors::{Cobs, Flavor, Slice};
+use serde::{Deserialize, Serialize};
+
+pub const CRC_GSM: Crc<u16> = Crc::<u16>::new(&CRC_16_GSM);
+
+const CRC_SIZE: usize = 2;
+
+/// Size of the largest frame a body of `body_size` bytes can turn into, including the COBS
+/// overhead and the terminating 0x00.
+pub const fn max_frame_size(body_size: usize) -> usize {
+    let encoded_size = body_size + CRC_SIZE;
+    encoded_size + encoded_size / 254 + 2
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FramingError {
+    /// The frame doesn't fit in the given buffer.
+    BufferFull,
+    /// The value could not be serialized.
+    Serialization,
+    /// The frame is not valid COBS, or too short to hold a checksum.
+    Cobs,
+    /// The checksum doesn't match the contents.
+    Crc,
+    /// The frame is intact, but doesn't hold the expected type.
+    Deserialization,
+    /// A fragment doesn't continue the message being reassembled, so that message is lost.
+    Fragment,
+}
+
+/// Postcard flavor that appends the CRC of everything serialized through it.
+struct Checksummed<'a, F: Flavor> {
+    flavor: F,
+    digest: Digest<'a, u16>,
+}
+
+impl<'a, F: Flavor> Flavor for Checksummed<'a, F> {
+    type Output = F::Output;
+
+    fn try_push(&mut self, data: u8) -> postcard::Result<()> {
+        self.digest.update(&[data]);
+        self.flavor.try_push(data)
+    }
+
+    fn finalize(mut self) -> postcard::Result<Self::Output> {
+        let crc = self.digest.finalize();
+        self.flavor.try_extend(&crc.to_le_bytes())?;
+        self.flavor.finalize()
+    }
+}
+
+/// Encodes `value` into a frame inside `buf`. Returns the used part of `buf`, terminator included.
+pub fn encode<'a, T: Serialize + ?Sized>(
+    value: &T,
+    buf: &'a mut [u8],
+) -> Result<&'a mut [u8], FramingError> {
+    let flavor = Checksummed {
+        flavor: Cobs::try_new(Slice::new(buf)).map_err(|_| FramingError::BufferFull)?,
+        digest: CRC_GSM.digest(),
+    };
+
+    postcard::serialize_with_flavor(value, flavor).map_err(serialization_error)
+}
+
+pub(crate) fn serialization_error(err: postcard::Error) -> FramingError {
+    match err {
+        postcard::Error::SerializeBufferFull => FramingError::BufferFull,
+        _ => FramingError::Serialization,
+    }
+}
+
+/// Decodes a received frame, checking its CRC. The frame gets decoded in place. The terminating
+/// 0x00 is optional.
+pub fn decode<'a, T: Deserialize<'a>>(frame: &'a mut [u8]) -> Result<T, FramingError> {
+    // The terminating 0x00 is not part of the COBS data.
+    let frame = match frame.last() {
+        Some(0x00) => {
+            let length = frame.len() - 1;
+            &mut frame[..length]
+        }
+        _ => frame,
+    };
+    let length = cobs::decode_in_place(frame).map_err(|_| FramingError::Cobs)?;
+    if length < CRC_SIZE {
+        return Err(FramingError::Cobs);
+    }
+
+    let (body, crc) = frame[..length].split_at(length - CRC_SIZE);
+    if CRC_GSM.checksum(body).to_le_bytes() != crc {
+        return Err(FramingError::Crc);
+    }
+
+    postcard::from_bytes(body).map_err(|_| FramingError::Deserialization)
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::{String, Vec};
+
+    use super::*;
+    use crate::comm::{
+        CommEvent, CommMessage, CommPacket, CommPacketType, ResponseTypeErr, ResponseTypeOk,
+    };
+    use crate::config::{
+        CalibrationPoint, ConfigPayload, ConfigResponse, ConfigSections, ConfigValue,
+        DisplayableVec, SamplePeriod, SensusConfigOld,
+    };
+    use crate::dfu::{DfuBlock, DfuPayload, DfuResponse};
+    use crate::sensors::{OnboardSample, ProbeSample, Reading};
+
+    fn round_trip<T>(value: &T) -> T
+    where
+        T: Serialize + for<'de> Deserialize<'de>,
+    {
+        let mut buf = [0u8; 512];
+        let frame = encode(value, &mut buf).unwrap();
+        assert_eq!(frame.last(), Some(&0x00));
+        assert!(!frame[..frame.len() - 1].contains(&0x00));
+        decode(frame).unwrap()
+    }
+
+    fn sample_config() -> SensusConfigOld {
+        SensusConfigOld {
+            sampling_period: SamplePeriod::default(),
+            name: String::from("Sensus"),
+            probe_calibration: DisplayableVec(
+                Vec::from_slice(&[100000.0, 1.0, 1700000.0, 0.0]).unwrap(),
+            ),
+        }
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        let packets = [
+            CommPacketType::GetLatestSensordata,
+            CommPacketType::GetDeviceInfo,
+            CommPacketType::ConfigPacket(ConfigPayload::ConfigSet(sample_config())),
+            CommPacketType::ConfigPacket(ConfigPayload::ConfigPatch(ConfigValue::Name(
+                String::from("Balcony"),
+            ))),
+            CommPacketType::ConfigPacket(ConfigPayload::CalibrationAdd(CalibrationPoint {
+                frequency: 900000,
+                percentage: 50,
+            })),
+            CommPacketType::DfuPacket(DfuPayload::Block(DfuBlock {
+                block_idx: 3,
+                data: Vec::from_slice(&[0x00; 64]).unwrap(),
+            })),
+        ];
+
+        for (id, payload) in packets.into_iter().enumerate() {
+            let packet = CommPacket {
+                id: id as u16,
+                payload,
+            };
+            assert_eq!(round_trip(&packet), packet);
+        }
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = [
+            CommMessage::ok(
+                7,
+                ResponseTypeOk::Config(ConfigResponse::GetConfig(sample_config())),
+            ),
+            CommMessage::ok(
+                8,
+                ResponseTypeOk::Dfu(DfuResponse::FirmwareVersion(String::from("v0.0.2"))),
+            ),
+            CommMessage::err(9, ResponseTypeErr::MacAddressNotInitialized),
+            CommMessage::Event(CommEvent::ConfigChanged(
+                ConfigSections::NAME | ConfigSections::FILTER,
+            )),
+            CommMessage::Event(CommEvent::ProbeData(Reading {
+                raw: ProbeSample {
+                    moisture: 41.0,
+                    moisture_raw: 950000.0,
+                    temperature: 21.5,
+                },
+                filtered: ProbeSample::default(),
+            })),
+            CommMessage::Event(CommEvent::OnboardData(Reading {
+                raw: OnboardSample::default(),
+                filtered: OnboardSample::default(),
+            })),
+        ];
+
+        for message in messages {
+            assert_eq!(round_trip(&message), message);
+        }
+    }
+
+    #[test]
+    fn frame_layout() {
+        let packet = CommPacket {
+            id: 0x0102,
+            payload: CommPacketType::GetMacAddress,
+        };
+        let mut buf = [0u8; 16];
+        let frame = encode(&packet, &mut buf).unwrap();
+
+        let mut raw = [0u8; 16];
+        let length = cobs::decode(&frame[..frame.len() - 1], &mut raw).unwrap();
+        // Request ID, message ID, body length, CRC.
+        let body = [0x02, 0x01, 0x03, 0x00];
+        assert_eq!(&raw[..4], &body);
+        assert_eq!(&raw[4..length], &CRC_GSM.checksum(&body).to_le_bytes());
+    }
+
+    #[test]
+    fn missing_terminator_is_accepted() {
+        let packet = CommPacket {
+            id: 1,
+            payload: CommPacketType::GetDeviceInfo,
+        };
+        let mut buf = [0u8; 16];
+        let length = encode(&packet, &mut buf).unwrap().len();
+
+        let decoded: CommPacket = decode(&mut buf[..length - 1]).unwrap();
+        assert_eq!(decoded, packet);
+    }
+
+    #[test]
+    fn corruption_is_detected() {
+        let packet = CommPacket {
+            id: 1,
+            payload: CommPacketType::ConfigPacket(ConfigPayload::ConfigSet(sample_config())),
+        };
+        let mut buf = [0u8; 128];
+        let length = encode(&packet, &mut buf).unwrap().len();
+
+        // Flip a bit somewhere in the middle, staying clear of COBS code bytes.
+        let mut corrupted = buf;
+        let index = (1..length - 1)
+            .find(|&i| corrupted[i] > 1 && corrupted[i] != 0x80 && i > 4)
+            .unwrap();
+        corrupted[index] ^= 0x01;
+        let result: Result<CommPacket, _> = decode(&mut corrupted[..length]);
+        assert!(matches!(
+            result,
+            Err(FramingError::Crc) | Err(FramingError::Cobs)
+        ));
+    }
+
+    #[test]
+    fn small_buffer_is_reported() {
+        let message = CommMessage::ok(
+            1,
+            ResponseTypeOk::Config(ConfigResponse::GetConfig(sample_config())),
+        );
+        let mut buf = [0u8; 16];
+        assert_eq!(encode(&message, &mut buf), Err(FramingError::BufferFull));
+    }
+
+    #[test]
+    fn frame_size_bound_holds() {
+        let message = CommMessage::ok(
+            1,
+            ResponseTypeOk::Config(ConfigResponse::GetConfig(sample_config())),
+        );
+        let mut body = [0u8; 256];
+        let body_size = postcard::to_slice(&message, &mut body).unwrap().len();
+
+        let mut buf = [0u8; 256];
+        let frame_size = encode(&message, &mut buf).unwrap().len();
+        assert!(frame_size <= max_frame_size(body_size));
+    }
+}
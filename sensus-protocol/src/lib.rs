@@ -0,0 +1,30 @@
+//! Types exchanged between Sensus and its host tools, along with the framing used to send them.
+//!
+//! Requests, responses and events are `tagged_enum!`s: each variant has a fixed message ID and its
+//! body is length-prefixed, so the declaration order doesn't matter and unknown messages can be
+//! skipped. Message IDs must never be reused. They are allocated in ranges:
+//!
+//! | IDs         | Enum              |
+//! |-------------|-------------------|
+//! | 0x00..0x10  | `CommPacketType`  |
+//! | 0x10..0x20  | `DfuPayload`      |
+//! | 0x20..0x30  | `ConfigPayload`   |
+//! | 0x30..0x40  | `CommEvent`       |
+//! | 0x40..0x50  | `ResponseTypeOk`  |
+//! | 0x50..0x60  | `ResponseTypeErr` |
+//! | 0x60..0x70  | `DfuResponse`     |
+//! | 0x70..0x80  | `ConfigResponse`  |
+//!
+//! Every other enum is encoded by postcard using the index of its variants, so variants may only
+//! ever be appended. Reordering or removing one breaks every host built against an older version.
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+#[macro_use]
+mod tagged;
+
+pub mod comm;
+pub mod config;
+pub mod dfu;
+pub mod fragment;
+pub mod framing;
+pub mod sensors;
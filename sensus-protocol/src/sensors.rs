@@ -0,0 +1,169 @@
+use core::ops::{Add, Mul, Sub};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct EnvironmentSample {
+    pub illuminance: f32,
+    pub temperature: f32,
+    pub humidity: f32,
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct BatteryLevel {
+    pub value: f32,
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct OnboardSample {
+    pub environment_data: EnvironmentSample,
+    pub battery_level: BatteryLevel,
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct ProbeSample {
+    pub moisture: f32,     // 0 - 100%
+    pub moisture_raw: f32, // Raw frequency measurement [Hz]
+    pub temperature: f32,  // °C
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SensorDataRaw {
+    pub onboard: OnboardSample,
+    pub probe: ProbeSample,
+}
+
+impl SensorDataRaw {
+    pub fn with_onboard(self, onboard_sample: OnboardSample) -> Self {
+        Self {
+            onboard: onboard_sample,
+            probe: self.probe,
+        }
+    }
+
+    pub fn with_probe(self, probe_sample: ProbeSample) -> Self {
+        Self {
+            onboard: self.onboard,
+            probe: probe_sample,
+        }
+    }
+}
+
+/// A fresh sample along with the filtered value it was folded into.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Reading<T> {
+    pub raw: T,
+    pub filtered: T,
+}
+
+pub type OnboardReading = Reading<OnboardSample>;
+pub type ProbeReading = Reading<ProbeSample>;
+
+// Necessary implementations to be able to filter the data.
+impl Add for EnvironmentSample {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        EnvironmentSample {
+            illuminance: self.illuminance + rhs.illuminance,
+            temperature: self.temperature + rhs.temperature,
+            humidity: self.humidity + rhs.humidity,
+        }
+    }
+}
+
+impl Sub for EnvironmentSample {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        EnvironmentSample {
+            illuminance: self.illuminance - rhs.illuminance,
+            temperature: self.temperature - rhs.temperature,
+            humidity: self.humidity - rhs.humidity,
+        }
+    }
+}
+
+impl Mul<f32> for EnvironmentSample {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        EnvironmentSample {
+            illuminance: self.illuminance * rhs,
+            temperature: self.temperature * rhs,
+            humidity: self.humidity * rhs,
+        }
+    }
+}
+
+impl Add for BatteryLevel {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        BatteryLevel {
+            value: self.value + rhs.value,
+        }
+    }
+}
+
+impl Sub for BatteryLevel {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        BatteryLevel {
+            value: self.value - rhs.value,
+        }
+    }
+}
+
+impl Mul<f32> for BatteryLevel {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        BatteryLevel {
+            value: self.value * rhs,
+        }
+    }
+}
+
+impl Add for ProbeSample {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        ProbeSample {
+            moisture: self.moisture + rhs.moisture,
+            temperature: self.temperature + rhs.temperature,
+            moisture_raw: self.moisture_raw + rhs.moisture_raw,
+        }
+    }
+}
+
+impl Sub for ProbeSample {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        ProbeSample {
+            moisture: self.moisture - rhs.moisture,
+            temperature: self.temperature - rhs.temperature,
+            moisture_raw: self.moisture_raw - rhs.moisture_raw,
+        }
+    }
+}
+
+impl Mul<f32> for ProbeSample {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        ProbeSample {
+            moisture: self.moisture * rhs,
+            temperature: self.temperature * rhs,
+            moisture_raw: self.moisture_raw * rhs,
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! Enums encoded with explicit message IDs instead of postcard's variant indices.
+//!
+//! A tagged variant goes on the wire as its message ID, followed by the length of its body and
+//! the postcard-encoded body itself. The ID never depends on the declaration order, and the length
+//! lets a decoder step over bodies it doesn't understand.
+
+use postcard::ser_flavors::Flavor;
+use serde::Serialize;
+
+/// Fixed numeric ID of a message. See the crate docs for how they are allocated.
+pub type MessageId = u16;
+
+/// Postcard flavor that only counts the bytes.
+struct Size(usize);
+
+impl Flavor for Size {
+    type Output = usize;
+
+    fn try_push(&mut self, _data: u8) -> postcard::Result<()> {
+        self.0 += 1;
+        Ok(())
+    }
+
+    fn try_extend(&mut self, data: &[u8]) -> postcard::Result<()> {
+        self.0 += data.len();
+        Ok(())
+    }
+
+    fn finalize(self) -> postcard::Result<usize> {
+        Ok(self.0)
+    }
+}
+
+/// Length of the postcard encoding of `body`. Encoded as a varint, just like postcard encodes the
+/// length of a byte slice, so the body can be read back as one.
+pub(crate) fn body_len<T: Serialize + ?Sized>(body: &T) -> postcard::Result<u32> {
+    postcard::serialize_with_flavor(body, Size(0)).map(|len| len as u32)
+}
+
+/// Declares an enum whose variants are encoded with the given message IDs.
+///
+/// Variants hold at most one value. An optional `_ => Variant` arm catches IDs this version
+/// doesn't know, keeping the ID. Without it, unknown IDs fail to decode.
+macro_rules! tagged_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $(
+                $(#[$vmeta:meta])*
+                $variant:ident $(($(#[$fmeta:meta])* $inner:ty))? = $id:literal,
+            )*
+            $(_ => $unknown:ident,)?
+        }
+    ) => {
+        $(#[$meta])*
+        pub enum $name {
+            $(
+                $(#[$vmeta])*
+                $variant $(($(#[$fmeta])* $inner))?,
+            )*
+            $(
+                /// Message ID of something this version doesn't know.
+                $unknown($crate::tagged::MessageId),
+            )?
+        }
+
+        impl $name {
+            /// Message IDs of the variants this version knows, in declaration order.
+            pub const IDS: &'static [$crate::tagged::MessageId] = &[$($id),*];
+
+            pub fn message_id(&self) -> $crate::tagged::MessageId {
+                match self {
+                    $(tagged_enum!(@pattern $name $variant _, $($inner)?) => $id,)*
+                    $($name::$unknown(id) => *id,)?
+                }
+            }
+        }
+
+        impl serde::Serialize for $name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                use serde::ser::{Error, SerializeTuple};
+
+                let mut tuple = serializer.serialize_tuple(3)?;
+                tuple.serialize_element(&self.message_id())?;
+                match self {
+                    $(
+                        tagged_enum!(@pattern $name $variant body, $($inner)?) => {
+                            tagged_enum!(@serialize tuple body $($inner)?)
+                        }
+                    )*
+                    $($name::$unknown(_) => tagged_enum!(@serialize tuple body),)?
+                }
+                tuple.end()
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                struct Visitor;
+
+                impl<'de> serde::de::Visitor<'de> for Visitor {
+                    type Value = $name;
+
+                    fn expecting(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+                        f.write_str(concat!("a tagged ", stringify!($name)))
+                    }
+
+                    fn visit_seq<A>(self, mut seq: A) -> Result<$name, A::Error>
+                    where
+                        A: serde::de::SeqAccess<'de>,
+                    {
+                        use serde::de::Error;
+
+                        let id: $crate::tagged::MessageId = seq
+                            .next_element()?
+                            .ok_or_else(|| Error::invalid_length(0, &self))?;
+                        let body: &'de [u8] = seq
+                            .next_element()?
+                            .ok_or_else(|| Error::invalid_length(1, &self))?;
+                        match id {
+                            $($id => tagged_enum!(@deserialize $name $variant body $($inner)?),)*
+                            id => tagged_enum!(@unknown $name id $($unknown)?),
+                        }
+                    }
+                }
+
+                deserializer.deserialize_tuple(2, Visitor)
+            }
+        }
+    };
+
+    (@pattern $name:ident $variant:ident $body:pat, $inner:ty) => { $name::$variant($body) };
+    (@pattern $name:ident $variant:ident $body:pat $(,)?) => { $name::$variant };
+
+    (@serialize $tuple:ident $body:ident $inner:ty) => {{
+        let len = $crate::tagged::body_len($body).map_err(S::Error::custom)?;
+        $tuple.serialize_element(&len)?;
+        $tuple.serialize_element($body)?;
+    }};
+    (@serialize $tuple:ident $body:ident) => {{
+        $tuple.serialize_element(&0u32)?;
+    }};
+
+    (@deserialize $name:ident $variant:ident $body:ident $inner:ty) => {
+        postcard::from_bytes::<$inner>($body)
+            .map($name::$variant)
+            .map_err(Error::custom)
+    };
+    (@deserialize $name:ident $variant:ident $body:ident) => { Ok($name::$variant) };
+
+    (@unknown $name:ident $id:ident $unknown:ident) => { Ok($name::$unknown($id)) };
+    (@unknown $name:ident $id:ident) => {
+        Err(Error::custom(::core::format_args!(
+            concat!("unknown ", stringify!($name), " message ID {}"),
+            $id
+        )))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::comm::{CommEvent, CommPacketType, ResponseTypeErr};
+    use crate::config::{ConfigField, ConfigPayload};
+    use crate::dfu::{DfuPayload, DfuResponse};
+
+    fn to_bytes<T: serde::Serialize>(value: &T) -> std::vec::Vec<u8> {
+        let mut buf = [0u8; 64];
+        postcard::to_slice(value, &mut buf).unwrap().to_vec()
+    }
+
+    #[test]
+    fn ids_and_lengths_are_explicit() {
+        let bytes = to_bytes(&CommPacketType::GetDeviceInfo);
+        assert_eq!(bytes, [0x05, 0x00]);
+
+        let packet = CommPacketType::ConfigPacket(ConfigPayload::ConfigGetField(ConfigField::Name));
+        let bytes = to_bytes(&packet);
+        // Outer ID and length, inner ID and length, then `ConfigField::Name`.
+        assert_eq!(bytes, [0x01, 0x03, 0x22, 0x01, 0x04]);
+
+        let response = DfuResponse::RequestBlock([0x34, 0x12]);
+        let bytes = to_bytes(&response);
+        assert_eq!(bytes, [0x61, 0x02, 0x34, 0x12]);
+    }
+
+    #[test]
+    fn supported_commands_follow_the_ids() {
+        assert_eq!(CommPacketType::IDS, [0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(CommPacketType::SUPPORTED_COMMANDS, 0xFF);
+        assert_eq!(DfuPayload::IDS, [0x10, 0x11, 0x12]);
+    }
+
+    #[test]
+    fn unknown_requests_keep_their_id() {
+        // ID 0x0123 with a body we know nothing about.
+        let bytes = [0xA3, 0x02, 0x03, 0xAA, 0xBB, 0xCC];
+        let packet: CommPacketType = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(packet, CommPacketType::Unsupported(0x0123));
+        assert_eq!(packet.unsupported(), Some(0x0123));
+
+        let bytes = [0x00, 0x02, 0x1F, 0x00];
+        let packet: CommPacketType = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            packet,
+            CommPacketType::DfuPacket(DfuPayload::Unsupported(0x1F))
+        );
+        assert_eq!(packet.unsupported(), Some(0x1F));
+
+        assert_eq!(CommPacketType::GetDeviceInfo.unsupported(), None);
+    }
+
+    #[test]
+    fn unknown_responses_are_rejected() {
+        assert!(postcard::from_bytes::<ResponseTypeErr>(&[0x5F, 0x00]).is_err());
+
+        // Events are optional, so they are kept instead.
+        let event: CommEvent = postcard::from_bytes(&[0x3F, 0x01, 0xFF]).unwrap();
+        assert_eq!(event, CommEvent::Unknown(0x3F));
+    }
+
+    #[test]
+    fn extra_body_bytes_are_ignored() {
+        // A newer firmware might extend a unit variant with a body.
+        let bytes = [0x12, 0x02, 0x01, 0x02];
+        let payload: DfuPayload = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(payload, DfuPayload::RequestFwVersion);
+    }
+}
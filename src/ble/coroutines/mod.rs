@@ -1,5 +1,9 @@
-use embassy_futures::select::{select, Either};
+use core::sync::atomic::Ordering::Relaxed;
+
+use embassy_futures::select::{select3, Either3};
 use heapless::Vec;
+#[cfg(feature = "ble-sec")]
+use nrf_softdevice::ble::security::SecurityHandler;
 use nrf_softdevice::{
     ble::{
         peripheral::{self, AdvertiseError},
@@ -8,19 +12,86 @@ use nrf_softdevice::{
     Softdevice,
 };
 
+#[cfg(feature = "ble-gatt-server")]
+use crate::ble::gatt;
 use crate::ble::types::AdvertismentData;
-use crate::ble::ADV_DATA;
+use crate::ble::{ADV_DATA, ADV_PARAMS_SIG};
+use crate::config_manager::SENSUS_CONFIG;
+use crate::power_manager::PLUGGED_IN_FLAG;
+
+/// Just Works pairing, without bonding. It encrypts the link, which centrals need before they may
+/// update or reconfigure us, see `comm_manager::access`.
+#[cfg(feature = "ble-sec")]
+struct Pairing;
+
+#[cfg(feature = "ble-sec")]
+impl SecurityHandler for Pairing {}
+
+#[cfg(feature = "ble-sec")]
+static PAIRING: Pairing = Pairing;
+
+/// Converts a TX power in dBm into the SoftDevice representation. The value was already checked
+/// against `TX_POWER_LEVELS_DBM` when the config was stored.
+fn tx_power_from_dbm(dbm: i8) -> TxPower {
+    match dbm {
+        -40 => TxPower::Minus40dBm,
+        -20 => TxPower::Minus20dBm,
+        -16 => TxPower::Minus16dBm,
+        -12 => TxPower::Minus12dBm,
+        -8 => TxPower::Minus8dBm,
+        -4 => TxPower::Minus4dBm,
+        0 => TxPower::ZerodBm,
+        3 => TxPower::Plus3dBm,
+        _ => TxPower::Plus4dBm,
+    }
+}
+
+/// Builds the advertising config for the current power state out of the global config.
+async fn advertising_config() -> peripheral::Config {
+    let params = SENSUS_CONFIG
+        .lock()
+        .await
+        .as_ref()
+        .map(|config| config.advertising.clone())
+        .unwrap_or_default();
+
+    let (interval_ms, tx_power_dbm) = match PLUGGED_IN_FLAG.load(Relaxed) {
+        true => (params.interval_plugged_ms, params.tx_power_plugged_dbm),
+        false => (params.interval_battery_ms, params.tx_power_battery_dbm),
+    };
 
+    peripheral::Config {
+        interval: interval_ms * 1000 / 625, // in units of 0.625ms
+        tx_power: tx_power_from_dbm(tx_power_dbm),
+        ..Default::default()
+    }
+}
+
+/// Advertises until a central connects, or forever if we aren't connectable.
+///
+/// With the GATT server enabled we are connectable whenever no central is connected yet. The
+/// connection is then handed over to the GATT task.
 async fn start_advertising<'a>(
     sd: &'static Softdevice,
+    config: &peripheral::Config,
     bthome_ad_element: Vec<u8, 31>,
     name_ad_element: Vec<u8, 31>,
 ) -> Result<(), AdvertiseError> {
-    let config = nrf_softdevice::ble::peripheral::Config {
-        interval: 1600, // equivalent to 1000ms
-        tx_power: TxPower::Plus4dBm,
-        ..Default::default()
-    };
+    #[cfg(feature = "ble-gatt-server")]
+    if !gatt::CONNECTED.load(Relaxed) {
+        let adv = peripheral::ConnectableAdvertisement::ScannableUndirected {
+            adv_data: bthome_ad_element.as_slice(),
+            scan_data: name_ad_element.as_slice(),
+        };
+        #[cfg(feature = "ble-sec")]
+        let conn = peripheral::advertise_pairable(sd, adv, config, &PAIRING).await?;
+        #[cfg(not(feature = "ble-sec"))]
+        let conn = peripheral::advertise_connectable(sd, adv, config).await?;
+        // Set right away, so that the next round advertises non-connectable.
+        gatt::CONNECTED.store(true, Relaxed);
+        gatt::NEW_CONNECTION.signal(conn);
+        return Ok(());
+    }
 
     let adv = peripheral::NonconnectableAdvertisement::ScannableUndirected {
         adv_data: bthome_ad_element.as_slice(), // The maximum size for Advertisment and Scan data is 31 bytes.
@@ -38,12 +109,12 @@ async fn start_advertising<'a>(
     //     adv_data: adv_data.as_slice(),
     //     anonymous: false,
     // };
-    // For now we will advertise as non-connectable.
-    peripheral::advertise(sd, adv, &config).await
+    peripheral::advertise(sd, adv, config).await
 }
 
 /// Starts the advertising loop. This loop watches for changes to ADV_DATA and publishes those new
-/// changes via extended advertisments.
+/// changes via extended advertisments. Changes to the advertising parameters are applied as soon
+/// as ADV_PARAMS_SIG is signalled, which also happens when a central disconnects.
 pub async fn advertisment_loop(sd: &'static Softdevice) {
     let mut advdata = AdvertismentData::default();
     loop {
@@ -51,13 +122,26 @@ pub async fn advertisment_loop(sd: &'static Softdevice) {
         // TODO. I need to somehow make it so that I detect at compile time AD elements longer than 31.
         defmt::trace!("BTHome AD length: {:?}", bthome_ad.len());
         let name_ad = advdata.get_ad_localname();
+        let config = advertising_config().await;
 
-        match select(ADV_DATA.wait(), start_advertising(sd, bthome_ad, name_ad)).await {
-            Either::First(newdata) => {
+        match select3(
+            ADV_DATA.wait(),
+            ADV_PARAMS_SIG.wait(),
+            start_advertising(sd, &config, bthome_ad, name_ad),
+        )
+        .await
+        {
+            Either3::First(newdata) => {
                 advdata = newdata;
                 defmt::trace!("New Advdata: {:?}", advdata);
             }
-            Either::Second(_e) => {
+            Either3::Second(_) => {
+                defmt::info!("Applying new advertising parameters.");
+            }
+            Either3::Third(Ok(())) => {
+                defmt::info!("Central connected.");
+            }
+            Either3::Third(Err(_e)) => {
                 defmt::error!("Advertisment error.");
             }
         }
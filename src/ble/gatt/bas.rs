@@ -0,0 +1,128 @@
+use core::cell::Cell;
+
+use nrf_softdevice::ble::gatt_server::builder::ServiceBuilder;
+use nrf_softdevice::ble::gatt_server::characteristic::{Attribute, Metadata, Properties};
+use nrf_softdevice::ble::gatt_server::{self, RegisterError};
+use nrf_softdevice::ble::{Connection, Uuid};
+use nrf_softdevice::Softdevice;
+
+use crate::sensors::types::BatteryLevel;
+use crate::sensors::{LATEST_SENSOR_DATA, SENSOR_DATA_UPDATED};
+
+// Assigned numbers of the Battery Service.
+const SERVICE_UUID: u16 = 0x180F;
+const BATTERY_LEVEL_UUID: u16 = 0x2A19;
+
+/// Battery voltages and the charge left at each of them, from a fresh 3V pack down to an empty
+/// one. 2.6V is also where BTHome starts reporting the battery as low.
+const BATTERY_CURVE: [(f32, u8); 6] = [
+    (3.0, 100),
+    (2.9, 80),
+    (2.8, 60),
+    (2.7, 40),
+    (2.6, 20),
+    (2.0, 0),
+];
+
+/// Remaining charge in %, interpolated linearly between the points of `BATTERY_CURVE`.
+fn battery_percentage(level: &BatteryLevel) -> u8 {
+    let (full_voltage, full) = BATTERY_CURVE[0];
+    if level.value >= full_voltage {
+        return full;
+    }
+    for points in BATTERY_CURVE.windows(2) {
+        let ((high_voltage, high), (low_voltage, low)) = (points[0], points[1]);
+        if level.value >= low_voltage {
+            let fraction = (level.value - low_voltage) / (high_voltage - low_voltage);
+            return low + (fraction * (high - low) as f32 + 0.5) as u8;
+        }
+    }
+    0
+}
+
+pub enum BasEvent {
+    CccdWrite { notifications: bool },
+}
+
+/// The Bluetooth SIG Battery Service. The level is derived from the measured battery voltage.
+pub struct BatteryService {
+    value_handle: u16,
+    cccd_handle: u16,
+}
+
+impl BatteryService {
+    pub fn new(sd: &mut Softdevice) -> Result<Self, RegisterError> {
+        let mut service = ServiceBuilder::new(sd, Uuid::new_16(SERVICE_UUID))?;
+        let metadata = Metadata::new(Properties::new().read().notify());
+        let level = service
+            .add_characteristic(
+                Uuid::new_16(BATTERY_LEVEL_UUID),
+                Attribute::new([0u8]),
+                metadata,
+            )?
+            .build();
+        service.build();
+
+        Ok(Self {
+            value_handle: level.value_handle,
+            cccd_handle: level.cccd_handle,
+        })
+    }
+
+    /// Keeps the battery level up to date for as long as the connection lasts, notifying it when
+    /// it changes.
+    pub async fn run(&self, conn: &Connection, notifications: &Cell<bool>) {
+        let sd = Softdevice::steal();
+        let mut updates = defmt::unwrap!(SENSOR_DATA_UPDATED.dyn_subscriber());
+        let mut current = None;
+
+        loop {
+            let level = LATEST_SENSOR_DATA
+                .lock()
+                .await
+                .as_ref()
+                .map(|data| battery_percentage(&data.onboard.battery_level));
+            if level.is_some() && level != current {
+                let value = [level.unwrap_or_default()];
+                if gatt_server::set_value(sd, self.value_handle, &value).is_err() {
+                    defmt::error!("[BAS] Failed to set the battery level.");
+                }
+                if notifications.get()
+                    && gatt_server::notify_value(conn, self.value_handle, &value).is_err()
+                {
+                    defmt::warn!("[BAS] Failed to notify the battery level.");
+                }
+                current = level;
+            }
+
+            updates.next_message().await;
+        }
+    }
+}
+
+impl gatt_server::Service for BatteryService {
+    type Event = BasEvent;
+
+    fn on_write(&self, handle: u16, data: &[u8]) -> Option<Self::Event> {
+        (handle == self.cccd_handle).then(|| BasEvent::CccdWrite {
+            notifications: data.first().map_or(false, |cccd| cccd & 0x01 != 0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{battery_percentage, BatteryLevel};
+
+    #[test]
+    fn battery_percentage_follows_the_curve() {
+        let percentage = |value| battery_percentage(&BatteryLevel { value });
+        assert_eq!(percentage(3.3), 100);
+        assert_eq!(percentage(3.0), 100);
+        assert_eq!(percentage(2.85), 70);
+        assert_eq!(percentage(2.6), 20);
+        assert_eq!(percentage(2.3), 10);
+        assert_eq!(percentage(1.8), 0);
+        assert_eq!(percentage(f32::NAN), 0);
+    }
+}
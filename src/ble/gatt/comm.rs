@@ -0,0 +1,178 @@
+use core::cell::Cell;
+
+use defmt::{error, Format};
+use embassy_futures::select::{select, Either};
+use embassy_sync::pubsub::WaitResult;
+use heapless::Vec;
+use nrf_softdevice::ble::gatt_server::NotifyValueError;
+use nrf_softdevice::ble::Connection;
+use nrf_softdevice::RawError;
+
+use crate::comm_manager::framing::{self, FramingError};
+use crate::comm_manager::transport::Transport;
+use crate::comm_manager::types::{CommMessage, PacketError};
+use crate::globals::{BLE_TX, EVENT_BUS, RX_BUS};
+
+use super::{notify_retrying, Server, ATT_VALUE_SIZE};
+
+/// Sizes frames are notified in chunks of, from the largest down. They fit the ATT MTUs centrals
+/// commonly negotiate: 247 bytes, 185 bytes on iOS and the default of 23 bytes if there was no
+/// exchange, each minus the 3 byte ATT header.
+const NOTIFICATION_SIZES: [usize; 3] = [ATT_VALUE_SIZE, 182, 20];
+/// Largest COBS frame we accept from the central.
+const MAX_RX_FRAME_SIZE: usize = 256;
+
+/// Carries the same COBS frames as the UART, as a byte stream: the central writes its packets to
+/// `rx` and gets our messages as notifications on `tx`. A frame may span several writes or
+/// notifications.
+///
+/// Uses the UUIDs of the Nordic UART Service, so that generic BLE UART apps can talk to it.
+#[nrf_softdevice::gatt_service(uuid = "6e400001-b5a3-f393-e0a9-e50e24dcca9e")]
+pub struct CommService {
+    #[characteristic(
+        uuid = "6e400002-b5a3-f393-e0a9-e50e24dcca9e",
+        write,
+        write_without_response
+    )]
+    rx: Vec<u8, ATT_VALUE_SIZE>,
+    #[characteristic(uuid = "6e400003-b5a3-f393-e0a9-e50e24dcca9e", notify)]
+    tx: Vec<u8, ATT_VALUE_SIZE>,
+}
+
+#[derive(Format, Debug, Clone, Copy)]
+enum GattError {
+    /// A message could not be turned into frames.
+    Encoding(FramingError),
+    Notify,
+}
+
+/// Splits the bytes written by the central into COBS frames.
+#[derive(Default)]
+pub struct RxFrames {
+    frame: Vec<u8, MAX_RX_FRAME_SIZE>,
+    overflow: bool,
+}
+
+impl RxFrames {
+    /// Takes the data of one write and hands every frame it completes to the comm manager.
+    pub fn push(&mut self, data: &[u8]) {
+        for &byte in data {
+            if !self.overflow && self.frame.push(byte).is_err() {
+                self.overflow = true;
+            }
+            if byte != 0x00 {
+                continue;
+            }
+
+            // Got end-of-frame.
+            let packet = match self.overflow {
+                true => Err(PacketError::PhysError),
+                false => framing::decode_packet(&mut self.frame),
+            };
+            // Called from the GATT event handler, so there's no waiting for room.
+            if RX_BUS.try_send((Transport::Ble, packet)).is_err() {
+                error!("[GATT] RX_BUS full. Dropping packet.");
+            }
+            self.frame.clear();
+            self.overflow = false;
+        }
+    }
+}
+
+/// Notifies a frame in as few chunks as the ATT MTU of the connection allows.
+///
+/// The SoftDevice doesn't tell us the MTU, but rejects notifications that exceed it. So we start
+/// out with the largest chunks and move on to smaller ones until they go through. The size that
+/// worked is kept in `chunk_size` for the rest of the connection.
+async fn notify_frame(
+    server: &Server,
+    conn: &Connection,
+    frame: &[u8],
+    chunk_size: &Cell<usize>,
+) -> Result<(), GattError> {
+    let mut rest = frame;
+    while !rest.is_empty() {
+        let (chunk, tail) = rest.split_at(chunk_size.get().min(rest.len()));
+        let value: Vec<u8, ATT_VALUE_SIZE> = defmt::unwrap!(Vec::from_slice(chunk));
+        match notify_retrying(|| server.comm.tx_notify(conn, &value)).await {
+            Ok(_) => rest = tail,
+            Err(NotifyValueError::Raw(RawError::DataSize)) => {
+                let smaller = NOTIFICATION_SIZES
+                    .into_iter()
+                    .find(|&size| size < chunk_size.get())
+                    .ok_or(GattError::Notify)?;
+                chunk_size.set(smaller);
+            }
+            Err(_) => return Err(GattError::Notify),
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends a message as notifications, split into as many COBS-encoded frames as needed.
+///
+/// * `number` - Message number of the fragments. Increment it for every message.
+/// * `chunk_size` - Notification size that fits the ATT MTU, see `notify_frame`.
+async fn send_message(
+    server: &Server,
+    conn: &Connection,
+    message: &CommMessage,
+    number: u8,
+    chunk_size: &Cell<usize>,
+) -> Result<(), GattError> {
+    let mut message_buf = [0u8; framing::MAX_MESSAGE_SIZE];
+    let fragments = framing::fragment_message(
+        message,
+        number,
+        framing::MAX_FRAGMENT_SIZE,
+        &mut message_buf,
+    )
+    .map_err(GattError::Encoding)?;
+
+    for fragment in fragments {
+        let mut buf = [0u8; framing::MAX_FRAME_SIZE];
+        let frame = framing::encode_fragment(&fragment, &mut buf).map_err(GattError::Encoding)?;
+        notify_frame(server, conn, frame, chunk_size).await?;
+    }
+
+    Ok(())
+}
+
+/// Sends the responses to the requests of this connection, and the events meant for every host.
+pub async fn tx_loop(server: &Server, conn: &Connection, notifications: &Cell<bool>) {
+    let mut events = EVENT_BUS
+        .subscriber()
+        .expect("Error registering subscriber for EVENT_BUS.");
+    // Answers meant for a previous connection.
+    while BLE_TX.try_recv().is_ok() {}
+    let chunk_size = Cell::new(NOTIFICATION_SIZES[0]);
+    let mut number: u8 = 0;
+    loop {
+        let message = match select(BLE_TX.recv(), events.next_message()).await {
+            Either::First(response) => response,
+            Either::Second(WaitResult::Message(event)) => CommMessage::Event(event),
+            Either::Second(WaitResult::Lagged(x)) => {
+                error!("[GATT] Missed {:?} events.", x);
+                continue;
+            }
+        };
+        if !notifications.get() {
+            // Nobody is listening.
+            continue;
+        }
+
+        let mut result = send_message(server, conn, &message, number, &chunk_size).await;
+        number = number.wrapping_add(1);
+        if let Err(GattError::Encoding(err)) = result {
+            error!("[GATT] Failed to encode {:?}: {:?}", message, err);
+            if let Some(fallback) = framing::encoding_failed(&message) {
+                result = send_message(server, conn, &fallback, number, &chunk_size).await;
+                number = number.wrapping_add(1);
+            }
+        }
+        if let Err(err) = result {
+            error!("[GATT] Failed to send message: {:?}", err);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use core::cell::Cell;
+
+use defmt::error;
+use heapless::Vec;
+use nrf_softdevice::ble::Connection;
+
+use crate::comm_manager::transport::Transport;
+use crate::comm_manager::types::{CommMessage, CommPacket, CommPacketType, PacketError};
+use crate::dfu::types::DfuPayload;
+use crate::globals::{BLE_DFU_TX, RX_BUS};
+
+use super::{notify_retrying, Server, ATT_VALUE_SIZE};
+
+/// Firmware updates over the air, driven by the same DFU state machine as over UART.
+///
+/// The central writes postcard-encoded `DfuPayload`s to `payload`, preferably without response,
+/// and gets the `CommResponse`s to them as notifications on `response`, `RequestBlock` included.
+/// A block needs an ATT MTU of at least 75 bytes, and the central has to accept the connection
+/// parameters of `ble::conn_params`, or blocks may time out.
+///
+/// Like config changes, updates are only taken once the central paired with us, see
+/// `comm_manager::access`.
+#[nrf_softdevice::gatt_service(uuid = "d1f0e001-7c2a-4b8e-9c31-53454e535553")]
+pub struct DfuService {
+    #[characteristic(
+        uuid = "d1f0e002-7c2a-4b8e-9c31-53454e535553",
+        write,
+        write_without_response
+    )]
+    payload: Vec<u8, ATT_VALUE_SIZE>,
+    #[characteristic(uuid = "d1f0e003-7c2a-4b8e-9c31-53454e535553", notify)]
+    response: Vec<u8, ATT_VALUE_SIZE>,
+}
+
+/// Hands a written payload to the comm manager, just like a `DfuPacket` received over UART.
+pub fn on_payload(data: &[u8]) {
+    // The writes carry no request ID, and only one DFU runs at a time anyway.
+    let packet = postcard::from_bytes::<DfuPayload>(data)
+        .map(|payload| CommPacket {
+            id: 0,
+            payload: CommPacketType::DfuPacket(payload),
+        })
+        .map_err(|_| PacketError::DeserializationError);
+    // Called from the GATT event handler, so there's no waiting for room.
+    if RX_BUS.try_send((Transport::BleDfu, packet)).is_err() {
+        error!("[GATT] RX_BUS full. Dropping DFU payload.");
+    }
+}
+
+/// Notifies the DFU state machine's responses for as long as the connection lasts.
+pub async fn tx_loop(server: &Server, conn: &Connection, notifications: &Cell<bool>) {
+    // Answers meant for a previous connection.
+    while BLE_DFU_TX.try_recv().is_ok() {}
+    loop {
+        let response = match BLE_DFU_TX.recv().await {
+            CommMessage::Response(_, response) => response,
+            // Events go out over the comm service.
+            CommMessage::Event(_) => continue,
+        };
+        if !notifications.get() {
+            continue;
+        }
+
+        let mut buf = [0u8; ATT_VALUE_SIZE];
+        let value = match postcard::to_slice(&response, &mut buf) {
+            Ok(bytes) => defmt::unwrap!(Vec::<u8, ATT_VALUE_SIZE>::from_slice(bytes)),
+            Err(_) => {
+                error!("[GATT] Failed to encode {:?}", response);
+                continue;
+            }
+        };
+        if notify_retrying(|| server.dfu.response_notify(conn, &value))
+            .await
+            .is_err()
+        {
+            error!("[GATT] Failed to notify DFU response.");
+        }
+    }
+}
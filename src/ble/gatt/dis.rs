@@ -0,0 +1,77 @@
+use core::fmt::Write;
+
+use heapless::String;
+use nrf_softdevice::ble::gatt_server::builder::ServiceBuilder;
+use nrf_softdevice::ble::gatt_server::characteristic::{Attribute, Metadata, Properties};
+use nrf_softdevice::ble::gatt_server::{self, RegisterError};
+use nrf_softdevice::ble::Uuid;
+use nrf_softdevice::Softdevice;
+
+use crate::ble::MAC_ADDRESS;
+use crate::FIRMWARE_VERSION;
+
+// Assigned numbers of the Device Information Service.
+const SERVICE_UUID: u16 = 0x180A;
+const MANUFACTURER_NAME_UUID: u16 = 0x2A29;
+const SERIAL_NUMBER_UUID: u16 = 0x2A25;
+const HARDWARE_REVISION_UUID: u16 = 0x2A27;
+const FIRMWARE_REVISION_UUID: u16 = 0x2A26;
+
+const MANUFACTURER_NAME: &str = "PlantBuddy";
+/// Same as `BoardVariant` in the device info.
+const HARDWARE_REVISION: &str = "nRF52832";
+
+/// Nothing in this service is writable.
+pub enum DisEvent {}
+
+/// The Bluetooth SIG Device Information Service. Its values never change, so they are only set
+/// when registering it.
+pub struct DeviceInformationService;
+
+impl DeviceInformationService {
+    pub fn new(sd: &mut Softdevice) -> Result<Self, RegisterError> {
+        let mut service = ServiceBuilder::new(sd, Uuid::new_16(SERVICE_UUID))?;
+        let serial_number = serial_number();
+        let values = [
+            (MANUFACTURER_NAME_UUID, MANUFACTURER_NAME),
+            (SERIAL_NUMBER_UUID, serial_number.as_str()),
+            (HARDWARE_REVISION_UUID, HARDWARE_REVISION),
+            (FIRMWARE_REVISION_UUID, FIRMWARE_VERSION),
+        ];
+        for (uuid, value) in values {
+            let metadata = Metadata::new(Properties::new().read());
+            service
+                .add_characteristic(
+                    Uuid::new_16(uuid),
+                    Attribute::new(value.as_bytes()),
+                    metadata,
+                )?
+                .build();
+        }
+        service.build();
+
+        Ok(Self)
+    }
+}
+
+impl gatt_server::Service for DeviceInformationService {
+    type Event = DisEvent;
+
+    fn on_write(&self, _handle: u16, _data: &[u8]) -> Option<Self::Event> {
+        None
+    }
+}
+
+/// The MAC address, which comes from the FICR, as hex digits with the most significant byte
+/// first. Empty if BLE isn't configured yet.
+fn serial_number() -> String<12> {
+    let mut serial = String::new();
+    // It's ok since MAC_ADDRESS is only written once, on startup.
+    if let Some(address) = unsafe { MAC_ADDRESS } {
+        for byte in address.bytes().iter().rev() {
+            // Six bytes always fit.
+            let _ = write!(serial, "{:02X}", byte);
+        }
+    }
+    serial
+}
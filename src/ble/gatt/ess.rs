@@ -0,0 +1,287 @@
+use core::cell::Cell;
+use core::sync::atomic::Ordering::Relaxed;
+
+use defmt::Format;
+use embassy_futures::select::{select, Either};
+use heapless::Vec;
+use nrf_softdevice::ble::gatt_server::builder::ServiceBuilder;
+use nrf_softdevice::ble::gatt_server::characteristic::{Attribute, Metadata, Properties};
+use nrf_softdevice::ble::gatt_server::{self, RegisterError};
+use nrf_softdevice::ble::{Connection, Uuid};
+use nrf_softdevice::Softdevice;
+
+use crate::config_manager::types::ConfigSections;
+use crate::globals::CONFIG_CHANGED;
+use crate::sensors::types::SensorDataRaw;
+use crate::sensors::{
+    LATEST_SENSOR_DATA, ONBOARD_SAMPLE_PERIOD, PROBE_SAMPLE_PERIOD, SENSOR_DATA_UPDATED,
+};
+
+// Assigned numbers of the Environmental Sensing Service.
+const SERVICE_UUID: u16 = 0x181A;
+const TEMPERATURE_UUID: u16 = 0x2A6E;
+const HUMIDITY_UUID: u16 = 0x2A6F;
+const ILLUMINANCE_UUID: u16 = 0x2AFB;
+const ES_MEASUREMENT_UUID: u16 = 0x290C;
+const ES_TRIGGER_SETTING_UUID: u16 = 0x290D;
+
+// ES Measurement descriptor fields.
+const SAMPLING_UNSPECIFIED: u8 = 0x00;
+const APPLICATION_UNSPECIFIED: u8 = 0x00;
+const APPLICATION_AIR: u8 = 0x01;
+const APPLICATION_SOIL: u8 = 0x04;
+const UNCERTAINTY_UNKNOWN: u8 = 0xFF;
+/// ES Trigger Setting condition: "no less than the specified time between transmissions".
+const TRIGGER_MIN_INTERVAL: u8 = 0x02;
+
+/// Number of characteristics, one per `Measurement`.
+pub const MEASUREMENT_COUNT: usize = 5;
+
+/// The quantities we expose. Soil moisture has no characteristic of its own, so it's a humidity
+/// characteristic whose ES Measurement descriptor names the soil as application.
+#[derive(Format, Clone, Copy, PartialEq, Eq)]
+pub enum Measurement {
+    AirTemperature,
+    AirHumidity,
+    Illuminance,
+    SoilTemperature,
+    SoilMoisture,
+}
+
+impl Measurement {
+    const ALL: [Measurement; MEASUREMENT_COUNT] = [
+        Measurement::AirTemperature,
+        Measurement::AirHumidity,
+        Measurement::Illuminance,
+        Measurement::SoilTemperature,
+        Measurement::SoilMoisture,
+    ];
+
+    fn uuid(self) -> u16 {
+        match self {
+            Measurement::AirTemperature | Measurement::SoilTemperature => TEMPERATURE_UUID,
+            Measurement::AirHumidity | Measurement::SoilMoisture => HUMIDITY_UUID,
+            Measurement::Illuminance => ILLUMINANCE_UUID,
+        }
+    }
+
+    fn application(self) -> u8 {
+        match self {
+            Measurement::AirTemperature | Measurement::AirHumidity => APPLICATION_AIR,
+            Measurement::SoilTemperature | Measurement::SoilMoisture => APPLICATION_SOIL,
+            Measurement::Illuminance => APPLICATION_UNSPECIFIED,
+        }
+    }
+
+    /// Sample period of the sensor behind this measurement, in milliseconds.
+    fn sample_period_ms(self) -> u32 {
+        match self {
+            Measurement::SoilTemperature | Measurement::SoilMoisture => {
+                PROBE_SAMPLE_PERIOD.load(Relaxed)
+            }
+            _ => ONBOARD_SAMPLE_PERIOD.load(Relaxed),
+        }
+    }
+
+    /// The characteristic value, in the units the SIG assigned to it.
+    fn encode(self, data: &SensorDataRaw) -> Vec<u8, 3> {
+        let environment = &data.onboard.environment_data;
+        let mut value = Vec::new();
+        let result = match self {
+            // sint16, 0.01 °C
+            Measurement::AirTemperature => {
+                value.extend_from_slice(&sint16(environment.temperature))
+            }
+            Measurement::SoilTemperature => {
+                value.extend_from_slice(&sint16(data.probe.temperature))
+            }
+            // uint16, 0.01 %
+            Measurement::AirHumidity => value.extend_from_slice(&uint16(environment.humidity)),
+            Measurement::SoilMoisture => value.extend_from_slice(&uint16(data.probe.moisture)),
+            // uint24, 0.01 lux
+            Measurement::Illuminance => value.extend_from_slice(&uint24(environment.illuminance)),
+        };
+        defmt::unwrap!(result);
+        value
+    }
+
+    /// ES Measurement descriptor: flags, sampling function, measurement period, update interval,
+    /// application and uncertainty.
+    fn es_measurement(self) -> [u8; 11] {
+        let interval = update_interval_s(self.sample_period_ms()).to_le_bytes();
+        [
+            0x00,
+            0x00,
+            SAMPLING_UNSPECIFIED,
+            0x00,
+            0x00,
+            0x00,
+            interval[0],
+            interval[1],
+            interval[2],
+            self.application(),
+            UNCERTAINTY_UNKNOWN,
+        ]
+    }
+
+    /// ES Trigger Setting descriptor. Values are notified when they change, at most once per
+    /// sample period.
+    fn es_trigger_setting(self) -> [u8; 4] {
+        let interval = update_interval_s(self.sample_period_ms()).to_le_bytes();
+        [TRIGGER_MIN_INTERVAL, interval[0], interval[1], interval[2]]
+    }
+}
+
+// Values in hundredths of their unit. Float to integer casts saturate, so out of range values are
+// clamped.
+fn sint16(value: f32) -> [u8; 2] {
+    ((value * 100.0) as i16).to_le_bytes()
+}
+
+fn uint16(value: f32) -> [u8; 2] {
+    ((value * 100.0) as u16).to_le_bytes()
+}
+
+/// 0xFFFFFE and above mean "out of range" and "unknown", so they are left out.
+fn uint24(value: f32) -> [u8; 3] {
+    let [b0, b1, b2, _] = ((value * 100.0) as u32).min(0xFF_FFFD).to_le_bytes();
+    [b0, b1, b2]
+}
+
+/// A sample period in whole seconds, rounded up and clamped to the uint24 of the descriptors.
+fn update_interval_s(period_ms: u32) -> u32 {
+    (period_ms / 1000 + (period_ms % 1000 != 0) as u32).min(0xFF_FFFF)
+}
+
+#[derive(Clone, Copy, Default)]
+struct Handles {
+    value: u16,
+    cccd: u16,
+    es_measurement: u16,
+    es_trigger_setting: u16,
+}
+
+pub enum EssEvent {
+    CccdWrite {
+        measurement: Measurement,
+        notifications: bool,
+    },
+}
+
+/// The Bluetooth SIG Environmental Sensing Service, fed from `LATEST_SENSOR_DATA`.
+pub struct EnvironmentalSensingService {
+    handles: [Handles; MEASUREMENT_COUNT],
+}
+
+impl EnvironmentalSensingService {
+    pub fn new(sd: &mut Softdevice) -> Result<Self, RegisterError> {
+        let mut service = ServiceBuilder::new(sd, Uuid::new_16(SERVICE_UUID))?;
+        let mut handles = [Handles::default(); MEASUREMENT_COUNT];
+
+        for (measurement, handles) in Measurement::ALL.into_iter().zip(handles.iter_mut()) {
+            let value = measurement.encode(&SensorDataRaw::default());
+            let metadata = Metadata::new(Properties::new().read().notify());
+            let mut characteristic = service.add_characteristic(
+                Uuid::new_16(measurement.uuid()),
+                Attribute::new(value.as_slice()),
+                metadata,
+            )?;
+            let es_measurement = characteristic.add_descriptor(
+                Uuid::new_16(ES_MEASUREMENT_UUID),
+                Attribute::new(measurement.es_measurement()),
+            )?;
+            let es_trigger_setting = characteristic.add_descriptor(
+                Uuid::new_16(ES_TRIGGER_SETTING_UUID),
+                Attribute::new(measurement.es_trigger_setting()),
+            )?;
+            let characteristic = characteristic.build();
+
+            *handles = Handles {
+                value: characteristic.value_handle,
+                cccd: characteristic.cccd_handle,
+                es_measurement: es_measurement.handle(),
+                es_trigger_setting: es_trigger_setting.handle(),
+            };
+        }
+        service.build();
+
+        Ok(Self { handles })
+    }
+
+    /// Rewrites the descriptors that depend on the sample periods.
+    fn update_descriptors(&self, sd: &Softdevice) {
+        for (measurement, handles) in Measurement::ALL.into_iter().zip(self.handles.iter()) {
+            let results = [
+                gatt_server::set_value(sd, handles.es_measurement, &measurement.es_measurement()),
+                gatt_server::set_value(
+                    sd,
+                    handles.es_trigger_setting,
+                    &measurement.es_trigger_setting(),
+                ),
+            ];
+            if results.iter().any(|result| result.is_err()) {
+                defmt::error!(
+                    "[ESS] Failed to update the descriptors of {:?}",
+                    measurement
+                );
+            }
+        }
+    }
+
+    /// Keeps the characteristics up to date with `LATEST_SENSOR_DATA` for as long as the
+    /// connection lasts, notifying the values that changed.
+    ///
+    /// * `notifications` - Whether notifications are enabled, per `Measurement`.
+    pub async fn run(&self, conn: &Connection, notifications: &[Cell<bool>; MEASUREMENT_COUNT]) {
+        let sd = Softdevice::steal();
+        let mut config_rx = defmt::unwrap!(CONFIG_CHANGED.dyn_subscriber());
+        let mut updates = defmt::unwrap!(SENSOR_DATA_UPDATED.dyn_subscriber());
+        let mut values: [Vec<u8, 3>; MEASUREMENT_COUNT] = Default::default();
+
+        self.update_descriptors(sd);
+        loop {
+            if let Some(data) = LATEST_SENSOR_DATA.lock().await.clone() {
+                for (index, measurement) in Measurement::ALL.into_iter().enumerate() {
+                    let value = measurement.encode(&data);
+                    if value == values[index] {
+                        continue;
+                    }
+                    let handle = self.handles[index].value;
+                    if gatt_server::set_value(sd, handle, &value).is_err() {
+                        defmt::error!("[ESS] Failed to set the value of {:?}", measurement);
+                    }
+                    if notifications[index].get()
+                        && gatt_server::notify_value(conn, handle, &value).is_err()
+                    {
+                        defmt::warn!("[ESS] Failed to notify {:?}", measurement);
+                    }
+                    values[index] = value;
+                }
+            }
+
+            match select(updates.next_message(), config_rx.next_message_pure()).await {
+                Either::First(_) => {}
+                Either::Second(sections) => {
+                    if sections.intersects(ConfigSections::SAMPLING_PERIOD) {
+                        self.update_descriptors(sd);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl gatt_server::Service for EnvironmentalSensingService {
+    type Event = EssEvent;
+
+    fn on_write(&self, handle: u16, data: &[u8]) -> Option<Self::Event> {
+        let index = self
+            .handles
+            .iter()
+            .position(|handles| handles.cccd == handle)?;
+        Some(EssEvent::CccdWrite {
+            measurement: Measurement::ALL[index],
+            notifications: data.first().map_or(false, |cccd| cccd & 0x01 != 0),
+        })
+    }
+}
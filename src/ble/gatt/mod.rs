@@ -0,0 +1,138 @@
+mod bas;
+mod comm;
+mod dfu;
+mod dis;
+mod ess;
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
+
+use embassy_futures::join::join4;
+use embassy_futures::select::select;
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, signal::Signal};
+use embassy_time::{Duration, Timer};
+use nrf_softdevice::ble::gatt_server::{self, NotifyValueError};
+use nrf_softdevice::ble::Connection;
+use nrf_softdevice::{RawError, Softdevice};
+use static_cell::StaticCell;
+
+use crate::ble::{link_encrypted, ADV_PARAMS_SIG, LINK_ENCRYPTED};
+use crate::comm_manager::set_sensor_stream;
+use crate::comm_manager::transport::Transport;
+
+use bas::{BasEvent, BatteryService};
+use comm::{CommService, CommServiceEvent, RxFrames};
+use dfu::{DfuService, DfuServiceEvent};
+use dis::DeviceInformationService;
+use ess::{EnvironmentalSensingService, EssEvent, MEASUREMENT_COUNT};
+
+#[nrf_softdevice::gatt_server]
+pub struct Server {
+    pub comm: CommService,
+    pub dfu: DfuService,
+    pub ess: EnvironmentalSensingService,
+    pub bas: BatteryService,
+    pub dis: DeviceInformationService,
+}
+
+/// Largest value of a single write or notification. Fills a 251 byte link layer packet.
+const ATT_VALUE_SIZE: usize = 244;
+/// How often a notification is retried while the SoftDevice is out of buffers.
+const NOTIFY_RETRIES: usize = 50;
+
+static SERVER: StaticCell<Server> = StaticCell::new();
+
+/// Set while a central is connected. We only support one connection, so meanwhile the advertising
+/// loop falls back to non-connectable advertising.
+pub static CONNECTED: AtomicBool = AtomicBool::new(false);
+/// Hands a new connection from the advertising loop over to the GATT task.
+pub static NEW_CONNECTION: Signal<ThreadModeRawMutex, Connection> = Signal::new();
+
+/// Registers our GATT services with the SoftDevice. Has to be done before the SoftDevice runs.
+pub fn register(sd: &mut Softdevice) -> &'static Server {
+    SERVER.init(defmt::unwrap!(Server::new(sd)))
+}
+
+/// Sends a notification, retrying while the SoftDevice has no buffer left for it.
+async fn notify_retrying(
+    mut notify: impl FnMut() -> Result<(), NotifyValueError>,
+) -> Result<(), NotifyValueError> {
+    for _ in 0..NOTIFY_RETRIES {
+        match notify() {
+            Err(NotifyValueError::Raw(RawError::Resources)) => {
+                Timer::after(Duration::from_millis(5)).await;
+            }
+            result => return result,
+        }
+    }
+    Err(NotifyValueError::Raw(RawError::Resources))
+}
+
+/// Serves a connection until the central disconnects.
+async fn serve(server: &Server, conn: &Connection) {
+    // Notifications stay off until the central subscribes to them.
+    let notifications = Cell::new(false);
+    let dfu_notifications = Cell::new(false);
+    let ess_notifications: [Cell<bool>; MEASUREMENT_COUNT] = Default::default();
+    let bas_notifications = Cell::new(false);
+    let mut rx = RxFrames::default();
+
+    let events = gatt_server::run(conn, server, |event| {
+        // Pairing may complete at any time, so look again before every write is handled.
+        LINK_ENCRYPTED.store(link_encrypted(conn), Relaxed);
+        match event {
+            ServerEvent::Comm(event) => match event {
+                CommServiceEvent::RxWrite(data) => rx.push(&data),
+                CommServiceEvent::TxCccdWrite {
+                    notifications: enabled,
+                } => notifications.set(enabled),
+            },
+            ServerEvent::Dfu(event) => match event {
+                DfuServiceEvent::PayloadWrite(data) => dfu::on_payload(&data),
+                DfuServiceEvent::ResponseCccdWrite {
+                    notifications: enabled,
+                } => dfu_notifications.set(enabled),
+            },
+            ServerEvent::Ess(EssEvent::CccdWrite {
+                measurement,
+                notifications: enabled,
+            }) => ess_notifications[measurement as usize].set(enabled),
+            ServerEvent::Bas(BasEvent::CccdWrite {
+                notifications: enabled,
+            }) => bas_notifications.set(enabled),
+            ServerEvent::Dis(event) => match event {},
+        }
+    });
+    let tasks = join4(
+        comm::tx_loop(server, conn, &notifications),
+        dfu::tx_loop(server, conn, &dfu_notifications),
+        server.ess.run(conn, &ess_notifications),
+        server.bas.run(conn, &bas_notifications),
+    );
+    select(events, tasks).await;
+}
+
+#[embassy_executor::task]
+pub async fn gatt_task(server: &'static Server) {
+    #[cfg(feature = "ble-l2cap")]
+    let dfu_endpoint = crate::ble::l2cap::DfuEndpoint::new(Softdevice::steal());
+    loop {
+        let conn = NEW_CONNECTION.wait().await;
+        defmt::info!("GATT client connected.");
+        // The DFU block timeout relies on these.
+        if conn.set_conn_params(crate::ble::conn_params()).is_err() {
+            defmt::warn!("Failed to request connection parameters.");
+        }
+        #[cfg(feature = "ble-l2cap")]
+        select(serve(server, &conn), dfu_endpoint.run(&conn)).await;
+        #[cfg(not(feature = "ble-l2cap"))]
+        serve(server, &conn).await;
+        defmt::info!("GATT client disconnected.");
+        set_sensor_stream(Transport::Ble, false);
+        LINK_ENCRYPTED.store(false, Relaxed);
+
+        CONNECTED.store(false, Relaxed);
+        // Become connectable again.
+        ADV_PARAMS_SIG.signal(());
+    }
+}
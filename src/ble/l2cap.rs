@@ -0,0 +1,241 @@
+//! Firmware updates over an L2CAP connection-oriented channel.
+//!
+//! Much faster than the DFU service of the GATT server: the central streams the image without
+//! waiting for a request per block. Flow control is credit-based. The SoftDevice only gives the
+//! central new credits as we take SDUs off the channel, so it is held back while a page is written.
+//!
+//! The first SDU on the channel is a postcard-encoded `DfuPayload::StartDfu`. The SDUs after it are
+//! the raw image, `binary_size` bytes split up any way the central likes. After every page written
+//! we send a postcard-encoded `CommResponse` with `DfuResponse::RequestBlock`, holding the number
+//! of `BLOCK_SIZE` blocks flashed so far. The transfer ends with `DfuResponse::DfuDone` followed by
+//! a reset, or with a `DfuError`, after which the channel takes a new `StartDfu`.
+//!
+//! Like every other update, images are only taken from centrals that paired with us, see
+//! `comm_manager::access`. Otherwise the `StartDfu` is answered with `InsecureLink`.
+
+use core::ptr::NonNull;
+
+use atomic_pool::{pool, Box};
+use defmt::{error, info, warn};
+use embassy_boot_nrf::{AlignedBuffer, FirmwareUpdater};
+use embassy_time::{with_timeout, Duration, Timer};
+use nrf_softdevice::ble::l2cap::{self, Channel, L2cap, SetupError};
+use nrf_softdevice::ble::Connection;
+use nrf_softdevice::Softdevice;
+
+use crate::ble::link_encrypted;
+use crate::comm_manager::access;
+use crate::comm_manager::transport::Transport;
+use crate::comm_manager::types::{CommPacketType, CommResponse, ResponseTypeErr, ResponseTypeOk};
+use crate::dfu::types::{DfuError, DfuPayload, DfuResponse, Page};
+use crate::FLASH_DRIVER;
+
+use sensus_protocol::dfu::{BLOCK_SIZE, L2CAP_DFU_PSM};
+
+/// Largest SDU we receive.
+const PACKET_MTU: usize = 512;
+/// Largest L2CAP frame, filling a 251 byte link layer packet.
+pub const PACKET_MPS: u16 = 247;
+/// Frames the central may send ahead of us. About two SDUs.
+const CREDITS: u16 = 5;
+/// How long the central may pause in the middle of a transfer.
+const RX_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The SoftDevice always holds one buffer to receive into. Add the SDU we're working on, the one
+// allocated while we're at it, and a response.
+pool!(PacketPool: [[u8; PACKET_MTU]; 4]);
+
+/// An SDU, in a buffer borrowed from `PacketPool`.
+struct Packet {
+    len: usize,
+    buf: Box<PacketPool>,
+}
+
+impl Packet {
+    /// Postcard-encodes a response into a new packet.
+    fn encode(response: &CommResponse) -> Option<Self> {
+        let mut buf = Box::<PacketPool>::new([0; PACKET_MTU])?;
+        let len = postcard::to_slice(response, &mut buf[..]).ok()?.len();
+        Some(Packet { len, buf })
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl l2cap::Packet for Packet {
+    const MTU: usize = PACKET_MTU;
+
+    fn allocate() -> Option<NonNull<u8>> {
+        Box::<PacketPool>::new([0; PACKET_MTU]).map(|buf| Box::into_raw(buf).cast::<u8>())
+    }
+
+    fn into_raw_parts(self) -> (NonNull<u8>, usize) {
+        (Box::into_raw(self.buf).cast::<u8>(), self.len)
+    }
+
+    unsafe fn from_raw_parts(ptr: NonNull<u8>, len: usize) -> Self {
+        Self {
+            len,
+            buf: Box::from_raw(ptr.cast::<[u8; PACKET_MTU]>()),
+        }
+    }
+}
+
+enum ImageError {
+    /// The channel is gone, so there's nobody left to tell.
+    Link,
+    Dfu(DfuError),
+    /// The central didn't pair with us.
+    InsecureLink,
+}
+
+/// Listens for DFU channels on `L2CAP_DFU_PSM`.
+pub struct DfuEndpoint {
+    l2cap: L2cap<Packet>,
+}
+
+impl DfuEndpoint {
+    pub fn new(sd: &Softdevice) -> Self {
+        Self {
+            l2cap: L2cap::init(sd),
+        }
+    }
+
+    /// Takes firmware images over DFU channels until the central disconnects.
+    pub async fn run(&self, conn: &Connection) {
+        let config = l2cap::Config { credits: CREDITS };
+        loop {
+            let channel = match self.l2cap.listen(conn, &config, L2CAP_DFU_PSM).await {
+                Ok(channel) => channel,
+                Err(SetupError::Disconnected) => return,
+                Err(_) => {
+                    warn!("[L2CAP] Failed to set up DFU channel.");
+                    continue;
+                }
+            };
+            info!("[L2CAP] DFU channel opened.");
+
+            loop {
+                let error = match receive_image(conn, &channel).await {
+                    Ok(_) => reset_into_update(&channel).await,
+                    Err(ImageError::Link) => break,
+                    Err(ImageError::Dfu(error)) => ResponseTypeErr::Dfu(error),
+                    Err(ImageError::InsecureLink) => ResponseTypeErr::InsecureLink,
+                };
+                warn!("[L2CAP] DFU failed: {:?}", error);
+                let response = CommResponse::Err(error);
+                if send(&channel, &response).await.is_err() {
+                    break;
+                }
+            }
+            info!("[L2CAP] DFU channel closed.");
+        }
+    }
+}
+
+async fn recv(channel: &Channel<Packet>) -> Result<Packet, ImageError> {
+    match with_timeout(RX_TIMEOUT, channel.rx()).await {
+        Ok(Ok(packet)) => Ok(packet),
+        Ok(Err(_)) => Err(ImageError::Link),
+        Err(_) => Err(ImageError::Dfu(DfuError::TimeoutError)),
+    }
+}
+
+async fn send(channel: &Channel<Packet>, response: &CommResponse) -> Result<(), ImageError> {
+    match Packet::encode(response) {
+        Some(packet) => channel.tx(packet).await.map_err(|_| ImageError::Link),
+        None => {
+            error!("[L2CAP] Failed to encode {:?}", response);
+            Ok(())
+        }
+    }
+}
+
+/// Flashes a full page and moves on to the next one.
+async fn write_page(updater: &mut FirmwareUpdater, page: &mut Page) -> Result<(), ImageError> {
+    let mut f = FLASH_DRIVER.lock().await;
+    let flash_ref = defmt::unwrap!(f.as_mut());
+    updater
+        .write_firmware(page.offset, page.data.as_slice(), flash_ref, page.length())
+        .await
+        .map_err(|_| ImageError::Dfu(DfuError::FlashError))?;
+
+    page.data.clear();
+    page.offset += 4096;
+    Ok(())
+}
+
+/// Streams an image from the channel into the DFU partition, reporting progress after every page.
+/// Once complete, the image is marked for the bootloader to swap in.
+async fn receive_image(conn: &Connection, channel: &Channel<Packet>) -> Result<(), ImageError> {
+    let header = match postcard::from_bytes::<DfuPayload>(recv(channel).await?.as_bytes()) {
+        Ok(DfuPayload::StartDfu(header)) => header,
+        _ => return Err(ImageError::Dfu(DfuError::StateMachineError)),
+    };
+    // Checked here rather than when the channel opens, since pairing may complete in between.
+    let payload = CommPacketType::DfuPacket(DfuPayload::StartDfu(header.clone()));
+    if !access::is_allowed(Transport::BleDfu, &payload, link_encrypted(conn)) {
+        return Err(ImageError::InsecureLink);
+    }
+    info!("[L2CAP] Receiving a {} byte image.", header.binary_size);
+    crate::dfu::check_image_size(&header).map_err(ImageError::Dfu)?;
+    let _update =
+        crate::dfu::UpdateGuard::acquire().ok_or(ImageError::Dfu(DfuError::StateMachineError))?;
+    // Someone updating us means the current firmware works.
+    crate::dfu::mark_booted().await;
+
+    let binary_size = header.binary_size as usize;
+    let mut updater = FirmwareUpdater::default();
+    let mut page = Page::new();
+    let mut received = 0;
+    while received < binary_size {
+        let sdu = recv(channel).await?;
+        let mut data = sdu.as_bytes();
+        received += data.len();
+        if received > binary_size {
+            return Err(ImageError::Dfu(DfuError::StateMachineError));
+        }
+
+        while !data.is_empty() {
+            let room = page.data.capacity() - page.length();
+            let (head, tail) = data.split_at(room.min(data.len()));
+            defmt::unwrap!(page.data.extend_from_slice(head));
+            data = tail;
+
+            if page.is_full() {
+                write_page(&mut updater, &mut page).await?;
+                let flashed_blocks = (page.offset / BLOCK_SIZE) as u16;
+                let response = CommResponse::Ok(ResponseTypeOk::Dfu(DfuResponse::RequestBlock(
+                    flashed_blocks.to_le_bytes(),
+                )));
+                send(channel, &response).await?;
+            }
+        }
+    }
+
+    // Images don't have to end on a page boundary. Pad the rest like erased flash.
+    if page.length() > 0 {
+        defmt::unwrap!(page.data.resize(page.data.capacity(), 0xFF));
+        write_page(&mut updater, &mut page).await?;
+    }
+
+    let mut f = FLASH_DRIVER.lock().await;
+    let flash_ref = defmt::unwrap!(f.as_mut());
+    let mut magic = AlignedBuffer([0u8; 4]);
+    updater
+        .mark_updated(flash_ref, magic.as_mut())
+        .await
+        .map_err(|_| ImageError::Dfu(DfuError::FlashError))
+}
+
+/// Reports success, then resets into the bootloader to swap in the new image.
+async fn reset_into_update(channel: &Channel<Packet>) -> ! {
+    let response = CommResponse::Ok(ResponseTypeOk::Dfu(DfuResponse::DfuDone));
+    // The image is marked for the bootloader either way.
+    let _ = send(channel, &response).await;
+    info!("DFU Done! Resetting...");
+    Timer::after(Duration::from_secs(1)).await;
+    cortex_m::peripheral::SCB::sys_reset();
+}
@@ -1,25 +1,73 @@
 use core::mem;
+use core::sync::atomic::AtomicBool;
 
-use embassy_futures::select::select;
 use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, signal::Signal};
-use nrf_softdevice::ble::Address;
+#[cfg(feature = "ble-sec")]
+use nrf_softdevice::ble::SecurityMode;
+use nrf_softdevice::ble::{Address, Connection};
 use nrf_softdevice::raw;
 use nrf_softdevice::Softdevice;
 
+use crate::comm_manager::types::SoftdeviceVersion;
+
 // Private modules
 mod macros;
 
 // Public modules
 pub mod coroutines;
+#[cfg(feature = "ble-gatt-server")]
+pub mod gatt;
+#[cfg(feature = "ble-l2cap")]
+pub mod l2cap;
 pub mod payload_manager;
 pub mod state_machines;
 pub mod types;
 // Exported variables
 pub static mut MAC_ADDRESS: Option<Address> = None;
+pub static mut SOFTDEVICE_VERSION: Option<SoftdeviceVersion> = None;
+/// Set while the connected central's link is encrypted, i.e. it paired with us. Decides whether
+/// it may update or reconfigure us, see `comm_manager::access`.
+pub static LINK_ENCRYPTED: AtomicBool = AtomicBool::new(false);
 
 // Synchronization variables
 /// Synchronizes new advertising data between state machine and advertising loop.
 static ADV_DATA: Signal<ThreadModeRawMutex, types::AdvertismentData> = Signal::new();
+/// Tells the advertising loop to re-read its interval and TX power from config.
+static ADV_PARAMS_SIG: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Connection intervals we ask every central for, in milliseconds. The DFU state machine derives
+/// its block timeout from the longest one, so centrals doing DFU have to accept them.
+pub const CONN_INTERVAL_MIN_MS: u32 = 15;
+pub const CONN_INTERVAL_MAX_MS: u32 = 45;
+/// Connection events we may skip. None, so that every request is answered right away.
+pub const CONN_SLAVE_LATENCY: u16 = 0;
+/// How long the link may stay silent before it counts as lost, in milliseconds.
+const CONN_SUPERVISION_TIMEOUT_MS: u32 = 4000;
+
+/// The connection parameters above, in SoftDevice units.
+pub fn conn_params() -> raw::ble_gap_conn_params_t {
+    raw::ble_gap_conn_params_t {
+        min_conn_interval: (CONN_INTERVAL_MIN_MS * 4 / 5) as u16, // in units of 1.25ms
+        max_conn_interval: (CONN_INTERVAL_MAX_MS * 4 / 5) as u16,
+        slave_latency: CONN_SLAVE_LATENCY,
+        conn_sup_timeout: (CONN_SUPERVISION_TIMEOUT_MS / 10) as u16, // in units of 10ms
+    }
+}
+
+/// True if the link is encrypted. Only centrals that paired with us get there.
+#[cfg(feature = "ble-sec")]
+pub fn link_encrypted(conn: &Connection) -> bool {
+    matches!(
+        conn.security_mode(),
+        SecurityMode::JustWorks | SecurityMode::Mitm | SecurityMode::LescMitm
+    )
+}
+
+/// Without `ble-sec` there's no pairing, so no link is ever encrypted.
+#[cfg(not(feature = "ble-sec"))]
+pub fn link_encrypted(_conn: &Connection) -> bool {
+    false
+}
 
 /// Configures BLE and returns a reference to the SoftDevice.
 pub fn configure_ble<'a>() -> &'a mut Softdevice {
@@ -35,6 +83,14 @@ pub fn configure_ble<'a>() -> &'a mut Softdevice {
             event_length: 24,
         }),
         conn_gatt: Some(raw::ble_gatt_conn_cfg_t { att_mtu: 256 }),
+        #[cfg(feature = "ble-l2cap")]
+        conn_l2cap: Some(raw::ble_l2cap_conn_cfg_t {
+            ch_count: 1,
+            rx_mps: l2cap::PACKET_MPS,
+            tx_mps: l2cap::PACKET_MPS,
+            rx_queue_size: 3,
+            tx_queue_size: 3,
+        }),
         gatts_attr_tab_size: Some(raw::ble_gatts_cfg_attr_tab_size_t {
             attr_tab_size: raw::BLE_GATTS_ATTR_TAB_SIZE_DEFAULT,
         }),
@@ -63,6 +119,22 @@ pub fn configure_ble<'a>() -> &'a mut Softdevice {
         MAC_ADDRESS.replace(mac_address);
     }
     defmt::info!("BLE MAC address: {:?}", mac_address);
+
+    let mut version: raw::ble_version_t = unsafe { mem::zeroed() };
+    let ret = unsafe { raw::sd_ble_version_get(&mut version) };
+    if ret == raw::NRF_SUCCESS {
+        let version = SoftdeviceVersion {
+            version_number: version.version_number,
+            company_id: version.company_id,
+            subversion_number: version.subversion_number,
+        };
+        defmt::info!("SoftDevice version: {:?}", version);
+        unsafe {
+            SOFTDEVICE_VERSION.replace(version);
+        }
+    } else {
+        defmt::error!("Error when reading the SoftDevice version: {}", ret);
+    }
     // Enable DC/DC converter for the Softdevice.
     unsafe {
         let ret =
@@ -73,16 +145,7 @@ pub fn configure_ble<'a>() -> &'a mut Softdevice {
     sd
 }
 
-static BLE_RESTART_SIG: Signal<ThreadModeRawMutex, bool> = Signal::new();
-
 #[embassy_executor::task]
 pub async fn ble_task() {
-    loop {
-        select(BLE_RESTART_SIG.wait(), self::state_machines::run()).await;
-    }
-}
-
-/// Restarts the BLE state machine.
-pub fn restart_state_machine() {
-    BLE_RESTART_SIG.signal(true);
+    self::state_machines::run().await;
 }
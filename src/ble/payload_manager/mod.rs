@@ -1,44 +1,92 @@
-use crate::sensors::types::SensorDataRaw;
-use crate::sensors::LATEST_SENSOR_DATA;
+use crate::comm_manager::stream_sensor_data;
+use crate::comm_manager::types::CommEvent;
+use crate::config_manager::types::EnabledSensors;
+use crate::config_manager::SENSUS_CONFIG;
+use crate::sensors::types::{OnboardSample, ProbeSample, SensorDataRaw};
+use crate::sensors::{LATEST_SENSOR_DATA, SENSOR_DATA_UPDATED};
 
 use embassy_futures::select::{select, Either};
 
 use crate::ble::types::BTHomeAD;
 use crate::globals::{BTHOME_QUEUE, ONBOARD_DATA_SIG, PROBE_DATA_SIG};
 
+/// Builds a BTHome payload out of the latest samples. Fields of disabled sensors are left out.
+fn build_bthome_ad(
+    onboard: Option<OnboardSample>,
+    probe: Option<ProbeSample>,
+    sensors: &EnabledSensors,
+) -> BTHomeAD {
+    let mut bthome_payload = BTHomeAD::default();
+
+    if let Some(data) = onboard {
+        if sensors.shtc3 {
+            bthome_payload = bthome_payload
+                .air_humidity((data.environment_data.humidity as u8).into())
+                .air_temperature(((data.environment_data.temperature * 10.0) as i16).into());
+        }
+        if sensors.opt3001 {
+            bthome_payload = bthome_payload
+                .illuminance(((data.environment_data.illuminance * 100.0) as u32).into());
+        }
+        if sensors.battery {
+            bthome_payload = bthome_payload
+                .battery_level(((data.battery_level.value * 1000.0) as u16).into())
+                .battery_low((data.battery_level.value <= 2.6f32).into());
+        }
+    }
+
+    if let Some(data) = probe {
+        if sensors.probe {
+            bthome_payload = bthome_payload
+                .soil_temperature(((data.temperature * 10.0) as i16).into())
+                .soil_humidity((data.moisture as u8).into());
+        }
+    }
+
+    bthome_payload
+}
+
 /// This loop receives data from different parts of the program and packs this data
 /// into an BTHomeAD. Then it sends this payload to be processed.
 async fn payload_mgr_loop() {
-    let mut bthome_payload = BTHomeAD::default();
+    let mut latest_onboard: Option<OnboardSample> = None;
+    let mut latest_probe: Option<ProbeSample> = None;
     let mut current_sensordata = SensorDataRaw::default();
     loop {
         // Wait for either new onboard data or new probe data.
-        bthome_payload = match select(ONBOARD_DATA_SIG.wait(), PROBE_DATA_SIG.wait()).await {
-            Either::First(data) => {
-                current_sensordata = current_sensordata.with_onboard(data);
-                bthome_payload
-                    .air_humidity((data.environment_data.humidity as u8).into())
-                    .air_temperature(((data.environment_data.temperature * 10.0) as i16).into())
-                    .illuminance(((data.environment_data.illuminance * 100.0) as u32).into())
-                    .battery_level(((data.battery_level.value * 1000.0) as u16).into())
-                    .battery_low((data.battery_level.value <= 2.6f32).into())
+        let event = match select(ONBOARD_DATA_SIG.wait(), PROBE_DATA_SIG.wait()).await {
+            Either::First(reading) => {
+                current_sensordata = current_sensordata.with_onboard(reading.filtered);
+                latest_onboard = Some(reading.filtered);
+                CommEvent::OnboardData(reading)
             }
-            Either::Second(data) => {
-                current_sensordata = current_sensordata.with_probe(data);
-                // Return a new advertisment payload.
-                bthome_payload
-                    .soil_temperature(((data.temperature * 10.0) as i16).into())
-                    .soil_humidity((data.moisture as u8).into())
+            Either::Second(reading) => {
+                current_sensordata = current_sensordata.with_probe(reading.filtered);
+                latest_probe = Some(reading.filtered);
+                CommEvent::ProbeData(reading)
             }
         };
+        stream_sensor_data(event);
         // Replace the latest sensor data with the filtered one.
         LATEST_SENSOR_DATA
             .lock()
             .await
             .replace(current_sensordata.clone());
+        SENSOR_DATA_UPDATED
+            .immediate_publisher()
+            .publish_immediate(());
+
+        // The switches are read every time, so that disabling a sensor also drops its stale data.
+        let sensors = SENSUS_CONFIG
+            .lock()
+            .await
+            .as_ref()
+            .map(|config| config.sensors)
+            .unwrap_or_default();
+        let bthome_payload = build_bthome_ad(latest_onboard, latest_probe, &sensors);
 
         // This call is debounced by the BLE state machine.
-        BTHOME_QUEUE.send(bthome_payload.clone()).await;
+        BTHOME_QUEUE.send(bthome_payload).await;
     }
 }
 
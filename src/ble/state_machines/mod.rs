@@ -1,19 +1,22 @@
 pub mod types;
 
+use embassy_futures::select::{select, Either};
 use embassy_time::{with_timeout, Duration};
 
+use crate::config_manager::types::ConfigSections;
 use crate::config_manager::SENSUS_CONFIG;
-use crate::globals::BTHOME_QUEUE;
+use crate::globals::{BTHOME_QUEUE, CONFIG_CHANGED};
 use types::{BleSM, BleSMState};
 
 use crate::ble::types::AdvertismentData;
-use crate::ble::ADV_DATA;
+use crate::ble::{ADV_DATA, ADV_PARAMS_SIG};
 
 /// Runst the Bluetooth state machine. This state machine waits for new data to be published and publishes said data
 /// via Extended Advertisments.
 pub async fn run() {
     let mut sm = BleSM::new();
     let mut current_adv_data = AdvertismentData::default();
+    let mut config_rx = defmt::unwrap!(CONFIG_CHANGED.dyn_subscriber());
 
     loop {
         match sm.state {
@@ -25,9 +28,23 @@ pub async fn run() {
                 sm = sm.with_state(BleSMState::WaitForAdvdata);
             }
             BleSMState::WaitForAdvdata => {
-                let bthome_ad = BTHOME_QUEUE.recv().await;
-                current_adv_data = current_adv_data.with_bthome(bthome_ad);
-                sm = sm.with_state(BleSMState::Debounce);
+                match select(BTHOME_QUEUE.recv(), config_rx.next_message_pure()).await {
+                    Either::First(bthome_ad) => {
+                        current_adv_data = current_adv_data.with_bthome(bthome_ad);
+                        sm = sm.with_state(BleSMState::Debounce);
+                    }
+                    Either::Second(sections) => {
+                        if sections.intersects(ConfigSections::ADVERTISING) {
+                            // Interval and TX power are applied by the advertising loop.
+                            ADV_PARAMS_SIG.signal(());
+                        }
+                        if sections.intersects(ConfigSections::NAME) {
+                            let config = SENSUS_CONFIG.lock().await.clone().unwrap_or_default();
+                            current_adv_data.set_name(config.name);
+                            sm = sm.with_state(BleSMState::Advertising);
+                        }
+                    }
+                }
             }
             // Debounce new received data so that I don't publish more often than every 250ms
             BleSMState::Debounce => {
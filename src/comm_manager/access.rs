@@ -0,0 +1,120 @@
+//! Which links may change the device.
+//!
+//! Anyone in range can connect over BLE, so commands that write the firmware or the stored config
+//! are only taken from encrypted BLE links, i.e. from centrals that paired with us. The `ble-dfu`
+//! feature lifts that for development. The UART takes physical access, so it is always trusted.
+
+use super::transport::Transport;
+use super::types::CommPacketType;
+use crate::config_manager::types::ConfigPayload;
+use crate::dfu::types::DfuPayload;
+
+/// True for commands that change the firmware or the stored config.
+pub fn is_privileged(payload: &CommPacketType) -> bool {
+    match payload {
+        CommPacketType::DfuPacket(payload) => {
+            matches!(payload, DfuPayload::StartDfu(_) | DfuPayload::Block(_))
+        }
+        CommPacketType::ConfigPacket(payload) => matches!(
+            payload,
+            ConfigPayload::ConfigSet(_)
+                | ConfigPayload::ConfigPatch(_)
+                | ConfigPayload::CalibrationAdd(_)
+                | ConfigPayload::CalibrationRemove(_)
+        ),
+        CommPacketType::FactoryReset(_) => true,
+        _ => false,
+    }
+}
+
+/// Decides whether a command may be acted upon, given the transport it came in on and whether the
+/// BLE link is encrypted.
+pub fn is_allowed(transport: Transport, payload: &CommPacketType, link_encrypted: bool) -> bool {
+    match transport {
+        Transport::Uart => true,
+        Transport::Ble | Transport::BleDfu => {
+            link_encrypted || cfg!(feature = "ble-dfu") || !is_privileged(payload)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_manager::types::{
+        CalibrationPoint, ConfigField, ConfigValue, DisplayableVec, ResetScope, SensusConfigOld,
+    };
+    use crate::dfu::types::{DfuBlock, DfuHeader};
+
+    fn privileged() -> [CommPacketType; 7] {
+        [
+            CommPacketType::DfuPacket(DfuPayload::StartDfu(DfuHeader {
+                binary_size: 64,
+                no_blocks: 1,
+            })),
+            CommPacketType::DfuPacket(DfuPayload::Block(DfuBlock {
+                block_idx: 0,
+                data: Default::default(),
+            })),
+            CommPacketType::ConfigPacket(ConfigPayload::ConfigSet(SensusConfigOld {
+                sampling_period: Default::default(),
+                name: Default::default(),
+                probe_calibration: DisplayableVec(Default::default()),
+            })),
+            CommPacketType::ConfigPacket(ConfigPayload::ConfigPatch(ConfigValue::Name(
+                Default::default(),
+            ))),
+            CommPacketType::ConfigPacket(ConfigPayload::CalibrationAdd(CalibrationPoint {
+                frequency: 100000,
+                percentage: 100,
+            })),
+            CommPacketType::ConfigPacket(ConfigPayload::CalibrationRemove(0)),
+            CommPacketType::FactoryReset(ResetScope::All),
+        ]
+    }
+
+    fn harmless() -> [CommPacketType; 7] {
+        [
+            CommPacketType::DfuPacket(DfuPayload::RequestFwVersion),
+            CommPacketType::ConfigPacket(ConfigPayload::ConfigGet),
+            CommPacketType::ConfigPacket(ConfigPayload::ConfigGetField(ConfigField::Name)),
+            CommPacketType::ConfigPacket(ConfigPayload::CalibrationList),
+            CommPacketType::GetLatestSensordata,
+            CommPacketType::GetDeviceInfo,
+            CommPacketType::SubscribeSensorData,
+        ]
+    }
+
+    #[test]
+    fn uart_takes_everything() {
+        for payload in privileged().iter().chain(harmless().iter()) {
+            assert!(is_allowed(Transport::Uart, payload, false));
+        }
+    }
+
+    #[test]
+    fn encrypted_ble_links_take_everything() {
+        for transport in [Transport::Ble, Transport::BleDfu] {
+            for payload in privileged().iter().chain(harmless().iter()) {
+                assert!(is_allowed(transport, payload, true));
+            }
+        }
+    }
+
+    #[test]
+    fn open_ble_links_only_take_harmless_commands() {
+        for transport in [Transport::Ble, Transport::BleDfu] {
+            for payload in privileged().iter() {
+                assert_eq!(
+                    is_allowed(transport, payload, false),
+                    cfg!(feature = "ble-dfu"),
+                    "{:?}",
+                    payload
+                );
+            }
+            for payload in harmless().iter() {
+                assert!(is_allowed(transport, payload, false), "{:?}", payload);
+            }
+        }
+    }
+}
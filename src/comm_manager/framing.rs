@@ -0,0 +1,56 @@
+//! Firmware side of the framing defined in `sensus_protocol::framing` and
+//! `sensus_protocol::fragment`.
+
+use sensus_protocol::fragment::{self, FRAGMENT_OVERHEAD};
+use sensus_protocol::framing::{self, max_frame_size};
+
+pub use sensus_protocol::fragment::{Fragment, Fragments, MAX_MESSAGE_SIZE};
+pub use sensus_protocol::framing::FramingError;
+
+use super::types::{CommMessage, CommPacket, PacketError, ResponseTypeErr};
+
+/// Most message bytes sent in a single frame. Larger messages are split into several fragments.
+pub const MAX_FRAGMENT_SIZE: usize = 64;
+/// Largest encoded frame, including the fragment header, the COBS overhead and the terminating 0x00.
+pub const MAX_FRAME_SIZE: usize = max_frame_size(MAX_FRAGMENT_SIZE + FRAGMENT_OVERHEAD);
+
+/// Decodes a received frame into a `CommPacket`, checking its CRC. The frame gets decoded in place.
+pub fn decode_packet(frame: &mut [u8]) -> Result<CommPacket, PacketError> {
+    framing::decode(frame).map_err(|err| match err {
+        FramingError::Crc => {
+            defmt::error!("Checksum error");
+            PacketError::PacketCRC
+        }
+        _ => PacketError::DeserializationError,
+    })
+}
+
+/// Encodes a message into `buf` and splits it into fragments of at most `fragment_size` bytes,
+/// which must not exceed `MAX_FRAGMENT_SIZE`. `number` is incremented for every message sent.
+pub fn fragment_message<'a>(
+    message: &CommMessage,
+    number: u8,
+    fragment_size: usize,
+    buf: &'a mut [u8; MAX_MESSAGE_SIZE],
+) -> Result<Fragments<'a>, FramingError> {
+    fragment::split(message, number, fragment_size, buf)
+}
+
+/// Encodes a fragment into a frame ready to be sent, and returns the used part of `buf`.
+pub fn encode_fragment<'a>(
+    fragment: &Fragment,
+    buf: &'a mut [u8; MAX_FRAME_SIZE],
+) -> Result<&'a [u8], FramingError> {
+    framing::encode(fragment, buf).map(|frame| &*frame)
+}
+
+/// What to send instead of a message that couldn't be encoded, so the host isn't left waiting for
+/// a response. Nothing for events, the host doesn't wait for those.
+pub fn encoding_failed(message: &CommMessage) -> Option<CommMessage> {
+    match message {
+        CommMessage::Response(id, _) => {
+            Some(CommMessage::err(*id, ResponseTypeErr::EncodingFailed))
+        }
+        CommMessage::Event(_) => None,
+    }
+}
@@ -1,97 +1,178 @@
+pub mod access;
+pub mod framing;
+pub mod transport;
 pub mod types;
+
 use crate::{
-    ble::MAC_ADDRESS,
-    globals::{RX_BUS, TX_BUS},
+    ble::{LINK_ENCRYPTED, MAC_ADDRESS, SOFTDEVICE_VERSION},
+    globals::{COMM_ACTIVITY, RX_BUS},
     sensors::LATEST_SENSOR_DATA,
-    FLASH_DRIVER,
+    BUILD_HASH, FIRMWARE_VERSION,
 };
-use embassy_boot_nrf::{AlignedBuffer, FirmwareUpdater};
-use types::{CommResponse, ResponseTypeErr};
+use core::str::FromStr;
+use core::sync::atomic::{AtomicU8, Ordering::Relaxed};
+use heapless::String;
+use transport::{Origin, Transport};
+use types::{BoardVariant, CommEvent, CommMessage, CommPacketType, DeviceInfo};
 
-/// This is the main Communication loop. It handles everything communication-related.
-/// Data comes in via a subscriber and gets sent away via a publisher.
-async fn comm_mgr_loop() {
-    let mut data_rx = RX_BUS
-        .dyn_subscriber()
-        .expect("Failed to acquire subscriber.");
-    let data_tx = TX_BUS
-        .dyn_publisher()
-        .expect("Failed to acquire publisher.");
+/// Transports subscribed to the sensor data stream, one `Transport::mask` bit each.
+static SENSOR_STREAM_SUBSCRIBERS: AtomicU8 = AtomicU8::new(0);
+
+/// Starts or stops streaming sensor data over `transport`. A transport that goes away, e.g. when
+/// the central disconnects, has to unsubscribe itself.
+pub fn set_sensor_stream(transport: Transport, active: bool) {
+    match active {
+        true => SENSOR_STREAM_SUBSCRIBERS.fetch_or(transport.mask(), Relaxed),
+        false => SENSOR_STREAM_SUBSCRIBERS.fetch_and(!transport.mask(), Relaxed),
+    };
+}
+
+/// Sends a sample to every transport subscribed to the sensor data stream, and only to those.
+pub fn stream_sensor_data(event: CommEvent) {
+    let subscribers = SENSOR_STREAM_SUBSCRIBERS.load(Relaxed);
+    for transport in Transport::ALL {
+        if subscribers & transport.mask() != 0 {
+            transport.send(CommMessage::Event(event.clone()));
+        }
+    }
+}
+
+/// The board this firmware was built for, picked by the board feature in Cargo.toml.
+#[cfg(feature = "nrf52832")]
+const BOARD: BoardVariant = BoardVariant::Nrf52832;
+#[cfg(not(feature = "nrf52832"))]
+compile_error!("No board selected. Enable a board feature, e.g. `nrf52832`.");
+
+/// Returns the optional features this firmware was built with.
+fn enabled_features() -> u8 {
+    let mut features = 0;
+    if cfg!(feature = "ble-gatt-server") {
+        features |= types::FEATURE_BLE_GATT_SERVER;
+    }
+    if cfg!(feature = "ble-gatt-client") {
+        features |= types::FEATURE_BLE_GATT_CLIENT;
+    }
+    if cfg!(feature = "ble-sec") {
+        features |= types::FEATURE_BLE_SEC;
+    }
+    if cfg!(feature = "ble-l2cap") {
+        features |= types::FEATURE_BLE_L2CAP;
+    }
+    if cfg!(feature = "extended-advertising") {
+        features |= types::FEATURE_EXTENDED_ADVERTISING;
+    }
+    if cfg!(feature = "ble-dfu") {
+        features |= types::FEATURE_BLE_DFU;
+    }
+    features
+}
 
-    let mut marked_booted = false;
+fn device_info() -> DeviceInfo {
+    // It's ok since MAC_ADDRESS and SOFTDEVICE_VERSION are only written once, on startup.
+    let (mac_address, softdevice) = unsafe {
+        (
+            MAC_ADDRESS.map(|address| address.bytes()),
+            SOFTDEVICE_VERSION,
+        )
+    };
 
+    DeviceInfo {
+        protocol_version: types::PROTOCOL_VERSION,
+        firmware_version: defmt::unwrap!(String::from_str(FIRMWARE_VERSION)),
+        build_hash: defmt::unwrap!(String::from_str(BUILD_HASH)),
+        mac_address,
+        supported_commands: CommPacketType::SUPPORTED_COMMANDS,
+        features: enabled_features(),
+        board: BOARD,
+        softdevice,
+    }
+}
+
+/// This is the main Communication loop. It handles everything communication-related.
+/// Packets from every transport come in over `RX_BUS`, and each response goes back over the
+/// transport its request came from.
+async fn comm_mgr_loop() {
     loop {
-        match data_rx.next_message_pure().await {
+        let (transport, packet_res) = RX_BUS.recv().await;
+        COMM_ACTIVITY.signal(transport);
+        match packet_res {
             Ok(packet) => {
+                let origin = Origin {
+                    transport,
+                    id: packet.id,
+                };
+                // Decoded fine, but from a newer host. Answer before anything acts on it.
+                if let Some(message_id) = packet.payload.unsupported() {
+                    defmt::warn!("[COMM_MANAGER] Unsupported command: {:#06x}", message_id);
+                    origin.err(types::ResponseTypeErr::UnsupportedCommand(message_id));
+                    continue;
+                }
+                if !access::is_allowed(transport, &packet.payload, LINK_ENCRYPTED.load(Relaxed)) {
+                    defmt::warn!(
+                        "[COMM_MANAGER] Rejecting a privileged command over an open link."
+                    );
+                    origin.err(types::ResponseTypeErr::InsecureLink);
+                    continue;
+                }
                 match packet.payload {
                     types::CommPacketType::DfuPacket(payload) => {
-                        if !marked_booted {
-                            // If we got a DFU message, we are clearly booted and at least the DFU
-                            // seems to be working.
-                            let mut updater = FirmwareUpdater::default();
-                            let mut magic = AlignedBuffer([0u8; 4]);
-                            let mut f = FLASH_DRIVER.lock().await;
-                            let flash_ref = defmt::unwrap!(f.as_mut());
-                            updater
-                                .mark_booted(flash_ref, magic.as_mut())
-                                .await
-                                .unwrap(); // Does not implement Format
-                            marked_booted = true;
-                            defmt::info!("DFU WAS SUCCESSFUL.");
-                        };
+                        // If we got a DFU message, we are clearly booted and at least the DFU
+                        // seems to be working.
+                        crate::dfu::mark_booted().await;
                         // Feed to DFU state machine for processing.
-                        crate::dfu::process_payload(payload).await;
+                        crate::dfu::process_payload(origin, payload).await;
                     }
                     types::CommPacketType::ConfigPacket(payload) => {
-                        crate::config_manager::process_payload(payload).await;
+                        crate::config_manager::process_payload(origin, payload).await;
                     }
                     types::CommPacketType::GetLatestSensordata => {
-                        match LATEST_SENSOR_DATA.try_lock() {
-                            Ok(data) => {
-                                let latest_data = &*data;
-                                let latest_data = latest_data.clone().unwrap_or_default();
-
-                                data_tx
-                                    .publish(CommResponse::Ok(types::ResponseTypeOk::SensorData(
-                                        latest_data,
-                                    )))
-                                    .await;
-                            }
-                            Err(_) => {
-                                data_tx
-                                    .publish(CommResponse::Err(
-                                        types::ResponseTypeErr::FailedToGetSensorData,
-                                    ))
-                                    .await;
-                            }
-                        };
+                        // The payload manager only holds the lock for a moment, so just wait for it.
+                        let latest_data =
+                            LATEST_SENSOR_DATA.lock().await.clone().unwrap_or_default();
+                        origin.ok(types::ResponseTypeOk::SensorData(latest_data));
                     }
                     types::CommPacketType::GetMacAddress => unsafe {
                         // It's ok since we only write MAC_ADDRESS once.
                         match MAC_ADDRESS {
                             Some(address) => {
-                                data_tx
-                                    .publish(CommResponse::Ok(types::ResponseTypeOk::MacAddress(
-                                        address.bytes(),
-                                    )))
-                                    .await;
+                                origin.ok(types::ResponseTypeOk::MacAddress(address.bytes()));
                             }
                             None => {
-                                data_tx
-                                    .publish(CommResponse::Err(
-                                        types::ResponseTypeErr::MacAddressNotInitialized,
-                                    ))
-                                    .await;
+                                origin.err(types::ResponseTypeErr::MacAddressNotInitialized);
                             }
                         }
                     },
+                    types::CommPacketType::GetDeviceInfo => {
+                        origin.ok(types::ResponseTypeOk::DeviceInfo(device_info()));
+                    }
+                    types::CommPacketType::SubscribeSensorData => {
+                        set_sensor_stream(transport, true);
+                        origin.ok(types::ResponseTypeOk::SensorStreamSubscribed);
+                    }
+                    types::CommPacketType::UnsubscribeSensorData => {
+                        set_sensor_stream(transport, false);
+                        origin.ok(types::ResponseTypeOk::SensorStreamUnsubscribed);
+                    }
+                    types::CommPacketType::FactoryReset(scope) => {
+                        match crate::config_manager::factory_reset(scope).await {
+                            Ok(_) => {
+                                origin.ok(types::ResponseTypeOk::FactoryReset(scope));
+                            }
+                            Err(err) => {
+                                defmt::error!("Factory reset failed: {:?}", err);
+                                origin.err(types::ResponseTypeErr::Config(err));
+                            }
+                        }
+                    }
+                    // Already answered above.
+                    types::CommPacketType::Unsupported(_) => {}
                 };
             }
             Err(err) => {
                 defmt::error!("[COMM_MANAGER] Packet Error: {:?}", err);
-                data_tx
-                    .publish(CommResponse::Err(ResponseTypeErr::Phys(err)))
-                    .await
+                // Without a decoded packet there's no request ID to answer to, but the transport
+                // is still known.
+                transport.send(CommMessage::Event(CommEvent::PacketError(err)));
             }
         }
     }
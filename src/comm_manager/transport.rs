@@ -0,0 +1,55 @@
+use defmt::Format;
+
+use super::types::{CommMessage, RequestId, ResponseTypeErr, ResponseTypeOk};
+use crate::globals::{BLE_DFU_TX, BLE_TX, UART_TX};
+
+/// The link a packet came in on. Its response goes back over the same link only.
+#[derive(Format, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Uart,
+    /// The comm service of the GATT server.
+    Ble,
+    /// The DFU service of the GATT server.
+    BleDfu,
+}
+
+impl Transport {
+    pub const ALL: [Transport; 3] = [Transport::Uart, Transport::Ble, Transport::BleDfu];
+
+    /// Bit of this transport in a set of transports.
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+
+    /// Queues a message for this transport only.
+    ///
+    /// Doesn't wait: if the transport isn't draining its queue, e.g. because it just went to sleep,
+    /// the message is dropped.
+    pub fn send(self, message: CommMessage) {
+        let queue = match self {
+            Transport::Uart => &UART_TX,
+            Transport::Ble => &BLE_TX,
+            Transport::BleDfu => &BLE_DFU_TX,
+        };
+        if queue.try_send(message).is_err() {
+            defmt::error!("[COMM_MANAGER] {:?} queue full. Dropping message.", self);
+        }
+    }
+}
+
+/// The request being answered: where it came from and its ID.
+#[derive(Format, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Origin {
+    pub transport: Transport,
+    pub id: RequestId,
+}
+
+impl Origin {
+    pub fn ok(self, response: ResponseTypeOk) {
+        self.transport.send(CommMessage::ok(self.id, response));
+    }
+
+    pub fn err(self, error: ResponseTypeErr) {
+        self.transport.send(CommMessage::err(self.id, error));
+    }
+}
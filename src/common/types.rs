@@ -42,6 +42,21 @@ impl<T: Copy + Default> Filter<T> {
         Self { value: None, alpha }
     }
 
+    /// Creates a filter whose step response reaches 99% within `settling_ms`, given that it gets
+    /// fed a new value every `sample_period_ms`. This way the smoothing stays the same no matter
+    /// the sample period. See `filter_alpha` for the formula.
+    ///
+    /// A settling time of 0 disables filtering altogether.
+    pub fn with_settling_time(sample_period_ms: u32, settling_ms: u32) -> Self {
+        Self::new(filter_alpha(sample_period_ms, settling_ms))
+    }
+
+    /// Recomputes the filter constant like `with_settling_time`, but keeps the current value so the
+    /// output doesn't jump when the config changes.
+    pub fn retune(&mut self, sample_period_ms: u32, settling_ms: u32) {
+        self.alpha = Self::with_settling_time(sample_period_ms, settling_ms).alpha;
+    }
+
     pub fn get_value(&self) -> Option<T> {
         self.value
     }
@@ -70,3 +85,68 @@ where
         defmt::unwrap!(self.get_value())
     }
 }
+
+/// Constant of an exponential filter fed a new value every `sample_period_ms`, chosen so that its
+/// step response reaches 99% within `settling_ms`: alpha = 1 - e^(-4 * period / settling).
+///
+/// A settling time of 0 disables filtering altogether, i.e. yields 1.
+fn filter_alpha(sample_period_ms: u32, settling_ms: u32) -> f32 {
+    if settling_ms == 0 {
+        return 1.0;
+    }
+
+    let ratio = sample_period_ms as f32 / settling_ms as f32;
+    (1.0 - exp_neg(4.0 * ratio)).clamp(0.0, 1.0)
+}
+
+/// Approximates e^(-x) for x >= 0, since we don't have libm. The argument gets halved until a short
+/// Taylor series is accurate enough, then the result is squared back up.
+fn exp_neg(x: f32) -> f32 {
+    // e^(-20) is far below what an f32 next to 1 can resolve. Also catches huge and NaN arguments,
+    // which would otherwise need more halvings than the series survives.
+    if x.is_nan() || x >= 20.0 {
+        return 0.0;
+    }
+
+    let mut y = x;
+    let mut halvings = 0;
+    while y > 0.01 {
+        y /= 2.0;
+        halvings += 1;
+    }
+
+    let mut result = 1.0 - y + y * y / 2.0 - y * y * y / 6.0;
+    for _ in 0..halvings {
+        result *= result;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{exp_neg, filter_alpha};
+
+    #[test]
+    fn exp_neg_holds_at_both_extremes() {
+        assert_eq!(exp_neg(0.0), 1.0);
+        assert!((exp_neg(1e-6) - 1.0).abs() < 1e-5);
+        assert!((exp_neg(1.0) - 0.367_879).abs() < 1e-4);
+        assert!((exp_neg(4.0) - 0.018_316).abs() < 1e-4);
+        assert!(exp_neg(19.9) >= 0.0);
+        assert_eq!(exp_neg(20.0), 0.0);
+        assert_eq!(exp_neg(1.6e10), 0.0);
+        assert_eq!(exp_neg(f32::INFINITY), 0.0);
+        assert_eq!(exp_neg(f32::NAN), 0.0);
+    }
+
+    #[test]
+    fn filter_alpha_stays_between_0_and_1() {
+        // The defaults the firmware used to hard-code.
+        assert!((filter_alpha(30_000, 300_000) - 0.3297).abs() < 1e-3);
+        assert_eq!(filter_alpha(30_000, 0), 1.0);
+        assert_eq!(filter_alpha(4_000_000_000, 1), 1.0);
+        assert_eq!(filter_alpha(u32::MAX, 1), 1.0);
+        let alpha = filter_alpha(1_000, u32::MAX);
+        assert!((0.0..1e-5).contains(&alpha));
+    }
+}
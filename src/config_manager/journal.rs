@@ -0,0 +1,191 @@
+//! Append-only journal of config records, spread over the two pages of the CONFIG region.
+//!
+//! Every committed page starts with a `PageHeader` holding a sequence number, followed by config
+//! records (see `record`) laid out back to back. The active page is the committed page with the
+//! highest sequence number, and the newest record in it that passes its CRC wins. Should none of
+//! its records pass, the other committed page is used.
+//!
+//! Storing a config appends a record to the active page. Only when the active page is full do we
+//! erase the other page, write the record into it and then commit it by writing its page header
+//! last. A brown-out at any point thus leaves the previously active page intact.
+
+use embassy_boot_nrf::AlignedBuffer;
+use embedded_storage_async::nor_flash::NorFlash;
+use nrf_softdevice::Flash;
+
+use super::record::{self, MAX_RECORD_SIZE, WORD_SIZE};
+use super::types::{ConfigError, PageHeader, SensusConfig};
+
+/// "SNSJ" when read as little-endian bytes.
+const PAGE_MAGIC: u32 = 0x4A53_4E53;
+pub const PAGE_SIZE: usize = 4096;
+/// The page that firmware predating the journal stored its single config record in.
+const LEGACY_PAGE: usize = 1;
+
+/// Result of walking the records of a page.
+struct PageScan {
+    /// The newest readable config, or the error of the newest record if none was readable.
+    config: Result<Option<SensusConfig>, ConfigError>,
+    /// Offset of the first free byte in the page.
+    free_offset: usize,
+}
+
+fn page(region: &[u8], index: usize) -> &[u8] {
+    &region[index * PAGE_SIZE..(index + 1) * PAGE_SIZE]
+}
+
+/// Returns the sequence number of a committed page.
+fn page_sequence(page: &[u8]) -> Option<u32> {
+    let header_bytes: &[u8; PageHeader::SIZE] = page[..PageHeader::SIZE].try_into().ok()?;
+    let header = PageHeader::from_bytes(header_bytes);
+
+    match header.magic == PAGE_MAGIC {
+        true => Some(header.sequence),
+        false => None,
+    }
+}
+
+/// Returns the index and sequence number of the active page, if any page was ever committed.
+fn active_page(region: &[u8]) -> Option<(usize, u32)> {
+    (0..region.len() / PAGE_SIZE)
+        .filter_map(|index| page_sequence(page(region, index)).map(|seq| (index, seq)))
+        .max_by_key(|(_, seq)| *seq)
+}
+
+fn scan(page: &[u8]) -> PageScan {
+    let mut config = Ok(None);
+    let mut offset = PageHeader::SIZE;
+
+    while offset + WORD_SIZE <= PAGE_SIZE {
+        let raw = &page[offset..];
+        if record::is_erased(raw) {
+            break;
+        }
+
+        match record::decode(raw) {
+            Ok((cfg, length)) => {
+                config = Ok(Some(cfg));
+                offset += length;
+            }
+            Err(err) => {
+                defmt::warn!(
+                    "Skipping unreadable config record at {:#x}: {:?}",
+                    offset,
+                    err
+                );
+                if !matches!(config, Ok(Some(_))) {
+                    config = Err(err);
+                }
+                match record::peek_len(raw) {
+                    Some(length) => offset += length,
+                    None => {
+                        // Garbage we can't skip. Treat the page as full so the next store moves on
+                        // to a freshly erased page.
+                        offset = PAGE_SIZE;
+                    }
+                }
+            }
+        }
+    }
+
+    PageScan {
+        config,
+        free_offset: offset.min(PAGE_SIZE),
+    }
+}
+
+/// Loads the newest config stored in `region`. A region that was never written to yields the
+/// default configuration.
+///
+/// If the active page holds nothing readable, the config last stored in the other committed page
+/// is used instead. Only when no committed page has a readable record is the error reported.
+pub fn load(region: &[u8]) -> Result<SensusConfig, ConfigError> {
+    // The CONFIG region is two pages.
+    let mut committed: heapless::Vec<(usize, u32), 2> = (0..region.len() / PAGE_SIZE)
+        .filter_map(|index| page_sequence(page(region, index)).map(|seq| (index, seq)))
+        .collect();
+    committed.sort_unstable_by_key(|(_, seq)| core::cmp::Reverse(*seq));
+
+    if let Some(&(newest, _)) = committed.first() {
+        let mut newest_error = None;
+        for &(index, _) in committed.iter() {
+            match scan(page(region, index)).config {
+                Ok(Some(config)) => {
+                    if index != newest {
+                        defmt::warn!("Config page {} unreadable. Using page {}.", newest, index);
+                    }
+                    return Ok(config);
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    newest_error.get_or_insert(err);
+                }
+            }
+        }
+        return match newest_error {
+            Some(err) => Err(err),
+            None => Ok(SensusConfig::default()),
+        };
+    }
+
+    // Nothing was journaled yet. Fall back to whatever older firmware left behind.
+    let raw = page(region, LEGACY_PAGE);
+    if record::is_erased(raw) {
+        Ok(SensusConfig::default())
+    } else if record::has_magic(raw) {
+        record::decode(raw).map(|(config, _)| config)
+    } else {
+        record::decode_legacy(raw)
+    }
+}
+
+/// Appends `config` to the journal in `region`, moving over to the other page if the active one
+/// is full.
+pub async fn append(
+    flash: &mut Flash,
+    region: &[u8],
+    config: &SensusConfig,
+) -> Result<(), ConfigError> {
+    let mut buf: AlignedBuffer<MAX_RECORD_SIZE> = AlignedBuffer([0; MAX_RECORD_SIZE]);
+    let length = record::encode(config, &mut buf.0)?;
+    let record = &buf.as_mut()[..length];
+    let region_start = region.as_ptr() as u32;
+    let page_addr = |index: usize| region_start + (index * PAGE_SIZE) as u32;
+
+    let (next_index, next_sequence) = match active_page(region) {
+        Some((index, sequence)) => {
+            let free_offset = scan(page(region, index)).free_offset;
+            if free_offset + length <= PAGE_SIZE {
+                return flash
+                    .write(page_addr(index) + free_offset as u32, record)
+                    .await
+                    .map_err(|e| ConfigError::Flash(e as u8));
+            }
+            defmt::info!("Config page {} full. Moving on to the next one.", index);
+            ((index + 1) % (region.len() / PAGE_SIZE), sequence + 1)
+        }
+        // Start in the page the legacy config is NOT stored in, so it survives until we're done.
+        None => ((LEGACY_PAGE + 1) % (region.len() / PAGE_SIZE), 1),
+    };
+
+    let start = page_addr(next_index);
+    flash
+        .erase(start, start + PAGE_SIZE as u32)
+        .await
+        .map_err(|e| ConfigError::Flash(e as u8))?;
+    flash
+        .write(start + PageHeader::SIZE as u32, record)
+        .await
+        .map_err(|e| ConfigError::Flash(e as u8))?;
+
+    // Committing the page header last makes the switch atomic.
+    let header = PageHeader {
+        magic: PAGE_MAGIC,
+        sequence: next_sequence,
+    };
+    let mut header_buf: AlignedBuffer<{ PageHeader::SIZE }> = AlignedBuffer(header.to_bytes());
+    flash
+        .write(start, header_buf.as_mut())
+        .await
+        .map_err(|e| ConfigError::Flash(e as u8))
+}
@@ -0,0 +1,361 @@
+//! Schema migrations for the stored `SensusConfig`.
+//!
+//! Whenever a field is added to or removed from `SensusConfig`, bump `CONFIG_VERSION`, freeze the
+//! previous layout in this file as `SensusConfigVn`, with private copies of its field types rather
+//! than the live ones, and register a `vN -> vN+1` step at the end of `MIGRATIONS`. Records written
+//! by older firmware then get upgraded on load instead of being replaced by the defaults.
+
+use heapless::{String, Vec};
+use postcard::{from_bytes, to_slice};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::types::{
+    AdvertisingParams, CalibrationPoint, ConfigError, EnabledSensors, FilterSettling,
+    ProbeCalibration, SamplePeriod, SensusConfig,
+};
+use super::CONFIG_SIZE;
+
+/// Current schema version of `SensusConfig`.
+pub const CONFIG_VERSION: u16 = 5;
+/// Schema version of the bare, headerless configs written by older firmware.
+pub const LEGACY_VERSION: u16 = 1;
+
+/// Upgrades a postcard-encoded config from version N to N+1. The upgraded payload is written to
+/// the second argument and its length returned.
+pub type Migration = fn(&[u8], &mut [u8]) -> Result<usize, ConfigError>;
+
+/// `MIGRATIONS[n - 1]` upgrades a version `n` payload to version `n + 1`.
+static MIGRATIONS: [Migration; CONFIG_VERSION as usize - 1] = [
+    upgrade::<SensusConfigV1, SensusConfigV2>,
+    upgrade::<SensusConfigV2, SensusConfigV3>,
+    upgrade::<SensusConfigV3, SensusConfigV4>,
+    upgrade::<SensusConfigV4, SensusConfig>,
+];
+
+// The layouts below are frozen, field types included, so that changes to the live types in
+// `super::types` can't change how old records decode. Each type is named after the version that
+// introduced it and reused unchanged by the versions after it.
+
+#[derive(Serialize, Deserialize)]
+struct SamplePeriodV1 {
+    #[serde(with = "postcard::fixint::le")]
+    onboard_sdt_plugged_ms: u32,
+    #[serde(with = "postcard::fixint::le")]
+    probe_sdt_plugged_ms: u32,
+    #[serde(with = "postcard::fixint::le")]
+    onboard_sdt_battery_ms: u32,
+    #[serde(with = "postcard::fixint::le")]
+    probe_sdt_battery_ms: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CalibrationPointV1 {
+    frequency: u32,
+    percentage: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProbeCalibrationV1 {
+    points: Vec<CalibrationPointV1, 10>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EnabledSensorsV2 {
+    probe: bool,
+    opt3001: bool,
+    shtc3: bool,
+    battery: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AdvertisingParamsV3 {
+    #[serde(with = "postcard::fixint::le")]
+    interval_plugged_ms: u32,
+    #[serde(with = "postcard::fixint::le")]
+    interval_battery_ms: u32,
+    tx_power_plugged_dbm: i8,
+    tx_power_battery_dbm: i8,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FilterSettlingV4 {
+    #[serde(with = "postcard::fixint::le")]
+    environment_settling_ms: u32,
+    #[serde(with = "postcard::fixint::le")]
+    battery_settling_ms: u32,
+    #[serde(with = "postcard::fixint::le")]
+    probe_settling_ms: u32,
+}
+
+/// Version 1: the original layout, without per-sensor switches.
+#[derive(Deserialize)]
+struct SensusConfigV1 {
+    sampling_period: SamplePeriodV1,
+    name: String<29>,
+    probe_calibration: ProbeCalibrationV1,
+}
+
+/// Version 2: added per-sensor switches.
+#[derive(Serialize, Deserialize)]
+struct SensusConfigV2 {
+    sampling_period: SamplePeriodV1,
+    name: String<29>,
+    probe_calibration: ProbeCalibrationV1,
+    sensors: EnabledSensorsV2,
+}
+
+impl From<SensusConfigV1> for SensusConfigV2 {
+    fn from(value: SensusConfigV1) -> Self {
+        SensusConfigV2 {
+            sampling_period: value.sampling_period,
+            name: value.name,
+            probe_calibration: value.probe_calibration,
+            // All sensors used to be on.
+            sensors: EnabledSensorsV2 {
+                probe: true,
+                opt3001: true,
+                shtc3: true,
+                battery: true,
+            },
+        }
+    }
+}
+
+/// Version 3: added advertising interval and TX power.
+#[derive(Serialize, Deserialize)]
+struct SensusConfigV3 {
+    sampling_period: SamplePeriodV1,
+    name: String<29>,
+    probe_calibration: ProbeCalibrationV1,
+    sensors: EnabledSensorsV2,
+    advertising: AdvertisingParamsV3,
+}
+
+impl From<SensusConfigV2> for SensusConfigV3 {
+    fn from(value: SensusConfigV2) -> Self {
+        SensusConfigV3 {
+            sampling_period: value.sampling_period,
+            name: value.name,
+            probe_calibration: value.probe_calibration,
+            sensors: value.sensors,
+            // What used to be hard-coded.
+            advertising: AdvertisingParamsV3 {
+                interval_plugged_ms: 1000,
+                interval_battery_ms: 1000,
+                tx_power_plugged_dbm: 4,
+                tx_power_battery_dbm: 4,
+            },
+        }
+    }
+}
+
+/// Version 4: added filter settling times.
+#[derive(Serialize, Deserialize)]
+struct SensusConfigV4 {
+    sampling_period: SamplePeriodV1,
+    name: String<29>,
+    probe_calibration: ProbeCalibrationV1,
+    sensors: EnabledSensorsV2,
+    advertising: AdvertisingParamsV3,
+    filter: FilterSettlingV4,
+}
+
+impl From<SensusConfigV3> for SensusConfigV4 {
+    fn from(value: SensusConfigV3) -> Self {
+        SensusConfigV4 {
+            sampling_period: value.sampling_period,
+            name: value.name,
+            probe_calibration: value.probe_calibration,
+            sensors: value.sensors,
+            advertising: value.advertising,
+            // The smoothing that used to be hard-coded, at the default sample periods.
+            filter: FilterSettlingV4 {
+                environment_settling_ms: 300_000,
+                battery_settling_ms: 600_000,
+                probe_settling_ms: 300_000,
+            },
+        }
+    }
+}
+
+/// The last step, from the frozen types into the live ones.
+impl From<SensusConfigV4> for SensusConfig {
+    fn from(value: SensusConfigV4) -> Self {
+        let period = value.sampling_period;
+        let points = value
+            .probe_calibration
+            .points
+            .into_iter()
+            .map(|point| CalibrationPoint {
+                frequency: point.frequency,
+                percentage: point.percentage,
+            })
+            .collect();
+        let sensors = value.sensors;
+        let advertising = value.advertising;
+        let filter = value.filter;
+
+        SensusConfig {
+            sampling_period: SamplePeriod {
+                onboard_sdt_plugged_ms: period.onboard_sdt_plugged_ms,
+                probe_sdt_plugged_ms: period.probe_sdt_plugged_ms,
+                onboard_sdt_battery_ms: period.onboard_sdt_battery_ms,
+                probe_sdt_battery_ms: period.probe_sdt_battery_ms,
+            },
+            name: value.name,
+            probe_calibration: ProbeCalibration::from_points(points),
+            sensors: EnabledSensors {
+                probe: sensors.probe,
+                opt3001: sensors.opt3001,
+                shtc3: sensors.shtc3,
+                battery: sensors.battery,
+            },
+            advertising: AdvertisingParams {
+                interval_plugged_ms: advertising.interval_plugged_ms,
+                interval_battery_ms: advertising.interval_battery_ms,
+                tx_power_plugged_dbm: advertising.tx_power_plugged_dbm,
+                tx_power_battery_dbm: advertising.tx_power_battery_dbm,
+            },
+            filter: FilterSettling {
+                environment_settling_ms: filter.environment_settling_ms,
+                battery_settling_ms: filter.battery_settling_ms,
+                probe_settling_ms: filter.probe_settling_ms,
+            },
+            serial: Default::default(),
+        }
+    }
+}
+
+/// Generic migration step for schemas that can be converted with a `From` implementation.
+fn upgrade<Old, New>(input: &[u8], output: &mut [u8]) -> Result<usize, ConfigError>
+where
+    Old: DeserializeOwned,
+    New: From<Old> + Serialize,
+{
+    let old: Old = from_bytes(input).map_err(|_| ConfigError::CorruptRecord)?;
+    let new: New = old.into();
+    let encoded = to_slice(&new, output).map_err(|_| ConfigError::SerializationError)?;
+    Ok(encoded.len())
+}
+
+/// Decodes a payload of the given schema version, running it through every migration needed to
+/// bring it up to `CONFIG_VERSION`.
+pub fn decode(version: u16, payload: &[u8]) -> Result<SensusConfig, ConfigError> {
+    if version == 0 || version > CONFIG_VERSION {
+        return Err(ConfigError::UnknownVersion(version));
+    }
+    if payload.len() > CONFIG_SIZE {
+        return Err(ConfigError::CorruptRecord);
+    }
+
+    // Ping-pong between two buffers, one migration at a time.
+    let mut current = [0u8; CONFIG_SIZE];
+    let mut next = [0u8; CONFIG_SIZE];
+    let mut length = payload.len();
+    current[..length].copy_from_slice(payload);
+
+    for migration in &MIGRATIONS[(version - 1) as usize..] {
+        length = migration(&current[..length], &mut next)?;
+        core::mem::swap(&mut current, &mut next);
+    }
+
+    from_bytes(&current[..length]).map_err(|_| ConfigError::CorruptRecord)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Version 1 fields: sample periods of 10s, 20s, 30s and 40s, the name "Sensus" and the
+    /// default calibration of 100kHz at 100% and 1.7MHz at 0%.
+    const V1: &[u8] = &[
+        0x10, 0x27, 0x00, 0x00, 0x20, 0x4E, 0x00, 0x00, 0x30, 0x75, 0x00, 0x00, 0x40, 0x9C, 0x00,
+        0x00, 0x06, b'S', b'e', b'n', b's', b'u', b's', 0x02, 0xA0, 0x8D, 0x06, 0x64, 0xA0, 0xE1,
+        0x67, 0x00,
+    ];
+    /// Version 2 adds the sensor switches: only the probe and the battery on.
+    const V2: &[u8] = &[0x01, 0x00, 0x00, 0x01];
+    /// Version 3 adds advertising: 500ms and 2s, at 0dBm and -4dBm.
+    const V3: &[u8] = &[0xF4, 0x01, 0x00, 0x00, 0xD0, 0x07, 0x00, 0x00, 0x00, 0xFC];
+    /// Version 4 adds settling times: off, 60s and 1s.
+    const V4: &[u8] = &[
+        0x00, 0x00, 0x00, 0x00, 0x60, 0xEA, 0x00, 0x00, 0xE8, 0x03, 0x00, 0x00,
+    ];
+    /// Version 5 adds serial timeouts: 200ms and 20ms.
+    const V5: &[u8] = &[0xC8, 0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00];
+
+    fn golden(version: u16) -> Vec<u8, 128> {
+        let parts = [V1, V2, V3, V4, V5];
+        let mut bytes = Vec::new();
+        for part in &parts[..version as usize] {
+            bytes.extend_from_slice(part).unwrap();
+        }
+        bytes
+    }
+
+    /// What a record of the given version decodes to.
+    fn expected(version: u16) -> SensusConfig {
+        let defaults = SensusConfig::default();
+        let mut config = SensusConfig {
+            sampling_period: SamplePeriod {
+                onboard_sdt_plugged_ms: 10_000,
+                probe_sdt_plugged_ms: 20_000,
+                onboard_sdt_battery_ms: 30_000,
+                probe_sdt_battery_ms: 40_000,
+            },
+            ..defaults
+        };
+        if version >= 2 {
+            config.sensors = EnabledSensors {
+                probe: true,
+                opt3001: false,
+                shtc3: false,
+                battery: true,
+            };
+        }
+        if version >= 3 {
+            config.advertising = AdvertisingParams {
+                interval_plugged_ms: 500,
+                interval_battery_ms: 2000,
+                tx_power_plugged_dbm: 0,
+                tx_power_battery_dbm: -4,
+            };
+        }
+        if version >= 4 {
+            config.filter = FilterSettling {
+                environment_settling_ms: 0,
+                battery_settling_ms: 60_000,
+                probe_settling_ms: 1000,
+            };
+        }
+        if version >= 5 {
+            config.serial.frame_timeout_ms = 200;
+            config.serial.byte_timeout_ms = 20;
+        }
+        config
+    }
+
+    #[test]
+    fn every_version_decodes_to_the_current_layout() {
+        for version in 1..=CONFIG_VERSION {
+            let config = decode(version, &golden(version)).unwrap();
+            assert!(config == expected(version), "version {}", version);
+        }
+    }
+
+    #[test]
+    fn the_current_layout_encodes_to_the_golden_bytes() {
+        let mut buf = [0u8; CONFIG_SIZE];
+        let encoded = to_slice(&expected(CONFIG_VERSION), &mut buf).unwrap();
+        assert_eq!(encoded, golden(CONFIG_VERSION).as_slice());
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        assert!(matches!(decode(0, V1), Err(ConfigError::UnknownVersion(0))));
+        assert!(matches!(
+            decode(CONFIG_VERSION + 1, V1),
+            Err(ConfigError::UnknownVersion(_))
+        ));
+    }
+}
@@ -4,7 +4,6 @@ use embassy_sync::{
     blocking_mutex::raw::ThreadModeRawMutex,
     mutex::{Mutex, TryLockError},
 };
-use embedded_storage_async::nor_flash::NorFlash;
 
 // Stores the current sensus configuration globally, so that other parts of the program can load it.
 pub static SENSUS_CONFIG: Mutex<ThreadModeRawMutex, Option<SensusConfig>> = Mutex::new(None);
@@ -14,56 +13,59 @@ const CONFIG_SIZE: usize = size_of::<types::SensusConfig>();
 // Public interfaces.
 pub mod types;
 
+// Private modules
+mod journal;
+mod migrations;
+mod record;
+
 use core::sync::atomic::Ordering::Relaxed;
-use embassy_boot_nrf::AlignedBuffer;
 use types::ConfigPayload;
 
 use crate::{
-    comm_manager::types::{CommResponse, ResponseTypeErr, ResponseTypeOk},
-    common,
-    globals::TX_BUS,
+    comm_manager::{
+        transport::Origin,
+        types::{CommEvent, ResponseTypeErr, ResponseTypeOk},
+    },
+    globals::{CONFIG_CHANGED, EVENT_BUS},
     power_manager::PLUGGED_IN_FLAG,
     sensors::{ONBOARD_SAMPLE_PERIOD, PROBE_SAMPLE_PERIOD},
+    serial::{BYTE_TIMEOUT_MS, FRAME_TIMEOUT_MS},
     FLASH_DRIVER,
 };
 
-use self::types::{ConfigError, ConfigResponse, SensusConfig};
+use self::types::{
+    ConfigError, ConfigResponse, ResetScope, SensusConfig, SERIAL_BYTE_TIMEOUT_MIN_MS,
+    SERIAL_FRAME_TIMEOUT_MIN_MS,
+};
 
 extern "C" {
     static __config_section_start__: u32;
     static __config_section_end__: u32;
 }
 
+/// Returns the raw contents of the CONFIG flash region.
+fn config_region() -> &'static [u8] {
+    unsafe {
+        let p_config_start: *const u32 = &__config_section_start__;
+        let p_config_end: *const u32 = &__config_section_end__;
+        let length = p_config_end as usize - p_config_start as usize;
+        core::slice::from_raw_parts(p_config_start as *const u8, length)
+    }
+}
+
 /// Stores a `SensusConfig` structure to flash.
 pub async fn store_sensus_config(config: types::SensusConfig) -> Result<(), ConfigError> {
     // Verifies if the fields are in the expected ranges.
     let config = config.verify()?;
 
-    if config == load_sensus_config() {
+    if load_sensus_config().ok().as_ref() == Some(&config) {
         defmt::info!("Config the same as stored config. Skipping rewrite.");
         return Ok(());
     }
 
-    let mut buf: AlignedBuffer<CONFIG_SIZE> = AlignedBuffer([0; CONFIG_SIZE]);
-    postcard::to_slice(&config, buf.as_mut()).map_err(|_| ConfigError::SerializationError)?;
-
     let mut f = FLASH_DRIVER.lock().await;
     let flash_ref = defmt::unwrap!(f.as_mut());
-
-    unsafe {
-        let p_config_start: *const u32 = &__config_section_start__;
-        let p_config_end: *const u32 = &__config_section_end__;
-
-        flash_ref
-            .erase(p_config_start as u32, p_config_end as u32)
-            .await
-            .map_err(|e| ConfigError::Flash(e as u8))?;
-
-        flash_ref
-            .write(p_config_start as u32, buf.as_mut())
-            .await
-            .map_err(|e| ConfigError::Flash(e as u8))?;
-    }
+    journal::append(flash_ref, config_region(), &config).await?;
 
     // Store a mirror image of the latest config in RAM.
     *SENSUS_CONFIG.lock().await = Some(config.clone());
@@ -73,68 +75,152 @@ pub async fn store_sensus_config(config: types::SensusConfig) -> Result<(), Conf
     Ok(())
 }
 
-/// Loads the saved configuration from flash and performs all necessary configurations.
-pub fn load_sensus_config() -> types::SensusConfig {
-    let buf = unsafe {
-        let p_config_start: *const u32 = &__config_section_start__;
-        let ptr = core::slice::from_raw_parts(p_config_start as *const u8, CONFIG_SIZE);
-        // I need to clone the data into a pointer found in the stack, otherwise I can't decode it in-place since
-        // the Flash is read-only.
-        let mut buf = [0u8; CONFIG_SIZE];
-        buf.clone_from_slice(ptr);
-        buf
-    };
-    let cfg: types::SensusConfig = postcard::from_bytes(&buf).unwrap_or_default();
+/// Loads the newest saved configuration from flash, upgrading it to the current schema if it was
+/// written by an older firmware. An erased CONFIG region yields the default configuration.
+///
+/// A stored calibration that doesn't pass the current rules is replaced with the default one, and
+/// out-of-range sample periods are clamped, so that patching the rest of the config keeps working.
+pub fn load_sensus_config() -> Result<types::SensusConfig, ConfigError> {
+    journal::load(config_region())
+        .map(|config| config.with_valid_calibration().with_valid_periods())
+}
 
-    // Restart all state machines that depend on configuration
-    match PLUGGED_IN_FLAG.load(Relaxed) {
-        true => {
-            ONBOARD_SAMPLE_PERIOD.store(cfg.sampling_period.onboard_sdt_plugged_ms, Relaxed);
-            PROBE_SAMPLE_PERIOD.store(cfg.sampling_period.probe_sdt_plugged_ms, Relaxed);
-        }
-        false => {
-            ONBOARD_SAMPLE_PERIOD.store(cfg.sampling_period.onboard_sdt_battery_ms, Relaxed);
-            PROBE_SAMPLE_PERIOD.store(cfg.sampling_period.probe_sdt_battery_ms, Relaxed);
-        }
+fn send_response_ok(origin: Origin, response: ConfigResponse) {
+    origin.ok(ResponseTypeOk::Config(response));
+}
+
+fn send_response_err(origin: Origin, err: ConfigError) {
+    origin.err(ResponseTypeErr::Config(err));
+}
+
+/// Stores a new config and tells the rest of the firmware which sections of it changed.
+async fn update_config(config: SensusConfig) -> Result<(), ConfigError> {
+    let previous = SENSUS_CONFIG.lock().await.clone().unwrap_or_default();
+    store_sensus_config(config).await?;
+    refresh_config().expect("Error refreshing config");
+
+    let changed = SENSUS_CONFIG
+        .lock()
+        .await
+        .as_ref()
+        .map(|current| previous.diff(current))
+        .unwrap_or_default();
+    if !changed.is_empty() {
+        CONFIG_CHANGED
+            .immediate_publisher()
+            .publish_immediate(changed);
+        // Let every host know as well, the change might have come in over another transport.
+        EVENT_BUS
+            .dyn_immediate_publisher()
+            .publish_immediate(CommEvent::ConfigChanged(changed));
     }
-    cfg
+    Ok(())
+}
+
+/// Restores the given part of the config to its defaults and notifies everything depending on it.
+pub async fn factory_reset(scope: ResetScope) -> Result<(), ConfigError> {
+    let config = match scope {
+        // A full reset must also work when the stored config is unreadable.
+        ResetScope::All => SensusConfig::default(),
+        _ => load_sensus_config()?.reset(scope),
+    };
+    defmt::warn!("Factory reset. Scope: {:?}", scope);
+    update_config(config).await
 }
 
-pub async fn process_payload(payload: ConfigPayload) {
+pub async fn process_payload(origin: Origin, payload: ConfigPayload) {
     // This process is simple, I don't actually need a state machine.
-    match payload {
+    let result = match payload {
         ConfigPayload::ConfigGet => {
-            let config = load_sensus_config();
-            TX_BUS
-                .dyn_immediate_publisher()
-                .publish_immediate(CommResponse::Ok(ResponseTypeOk::Config(
-                    ConfigResponse::GetConfig(config.into()),
-                )));
+            load_sensus_config().map(|config| ConfigResponse::GetConfig(config.into()))
         }
-        ConfigPayload::ConfigSet(new_cfg) => match store_sensus_config(new_cfg.into()).await {
-            Ok(_) => {
-                TX_BUS
-                    .dyn_immediate_publisher()
-                    .publish_immediate(CommResponse::Ok(ResponseTypeOk::Config(
-                        ConfigResponse::SetConfig,
-                    )));
-                refresh_config().expect("Error refreshing config");
-                common::restart_state_machines();
+        ConfigPayload::ConfigSet(new_cfg) => {
+            let current = load_sensus_config().unwrap_or_default();
+            match current.apply_old(new_cfg) {
+                Ok(config) => update_config(config)
+                    .await
+                    .map(|_| ConfigResponse::SetConfig),
+                Err(err) => Err(err),
             }
-            Err(err) => {
-                defmt::error!("Error when updating config: {:?}", err);
-                TX_BUS
-                    .dyn_immediate_publisher()
-                    .publish_immediate(CommResponse::Err(ResponseTypeErr::Config(err)));
+        }
+        ConfigPayload::ConfigGetField(field) => load_sensus_config()
+            .and_then(|config| config.get_field(field))
+            .map(ConfigResponse::GetField),
+        ConfigPayload::ConfigPatch(value) => {
+            let field = value.field();
+            match load_sensus_config().and_then(|config| config.patch(value)) {
+                Ok(config) => update_config(config)
+                    .await
+                    .map(|_| ConfigResponse::PatchConfig(field)),
+                Err(err) => Err(err),
             }
+        }
+        ConfigPayload::CalibrationList => load_sensus_config()
+            .map(|config| ConfigResponse::CalibrationPoints(config.probe_calibration.list())),
+        ConfigPayload::CalibrationAdd(point) => match load_sensus_config() {
+            Ok(mut config) => match config.probe_calibration.add(point) {
+                Ok(index) => update_config(config)
+                    .await
+                    .map(|_| ConfigResponse::CalibrationAdded(index)),
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err),
+        },
+        ConfigPayload::CalibrationRemove(index) => match load_sensus_config() {
+            Ok(mut config) => match config.probe_calibration.remove(index) {
+                Ok(_) => update_config(config)
+                    .await
+                    .map(|_| ConfigResponse::CalibrationRemoved(index)),
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err),
         },
+        // The comm manager answers these before they get here.
+        ConfigPayload::Unsupported(_) => return,
     };
+
+    match result {
+        Ok(response) => send_response_ok(origin, response),
+        Err(err) => {
+            defmt::error!("Error when processing config payload: {:?}", err);
+            send_response_err(origin, err);
+        }
+    }
 }
 
 /// Initializes the Config Manager. This needs to be called on boot.
+///
+/// A stored config that can't be read is reported and replaced by the defaults in RAM. The flash
+/// contents are left untouched until the next `ConfigSet`.
 pub fn refresh_config() -> Result<(), TryLockError> {
-    let config = load_sensus_config();
+    let config = load_sensus_config().unwrap_or_else(|err| {
+        defmt::error!("Stored config unreadable ({:?}). Using defaults.", err);
+        SensusConfig::default()
+    });
     defmt::info!("Loaded the following config: {:?}", config);
+
+    match PLUGGED_IN_FLAG.load(Relaxed) {
+        true => {
+            ONBOARD_SAMPLE_PERIOD.store(config.sampling_period.onboard_sdt_plugged_ms, Relaxed);
+            PROBE_SAMPLE_PERIOD.store(config.sampling_period.probe_sdt_plugged_ms, Relaxed);
+        }
+        false => {
+            ONBOARD_SAMPLE_PERIOD.store(config.sampling_period.onboard_sdt_battery_ms, Relaxed);
+            PROBE_SAMPLE_PERIOD.store(config.sampling_period.probe_sdt_battery_ms, Relaxed);
+        }
+    }
+
+    // Configs stored before the lower limits existed may still hold shorter timeouts.
+    let serial = &config.serial;
+    FRAME_TIMEOUT_MS.store(
+        serial.frame_timeout_ms.max(SERIAL_FRAME_TIMEOUT_MIN_MS),
+        Relaxed,
+    );
+    BYTE_TIMEOUT_MS.store(
+        serial.byte_timeout_ms.max(SERIAL_BYTE_TIMEOUT_MIN_MS),
+        Relaxed,
+    );
+
     *SENSUS_CONFIG.try_lock()? = Some(config);
     Ok(())
 }
@@ -0,0 +1,122 @@
+//! On-flash framing of a `SensusConfig`.
+//!
+//! A record looks like this, all fields little-endian:
+//!
+//! | magic (4) | version (2) | length (2) | crc (4) | payload (length) | 0xFF padding |
+//!
+//! The payload is a postcard-encoded `SensusConfig` of the given schema version. The CRC covers
+//! the version, the length and the payload. Records are padded to a multiple of 4 bytes, since
+//! that is the write granularity of the flash.
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+use super::migrations::{self, CONFIG_VERSION, LEGACY_VERSION};
+use super::types::{ConfigError, RecordHeader, SensusConfig};
+use super::CONFIG_SIZE;
+
+/// "SNSC" when read as little-endian bytes.
+pub const RECORD_MAGIC: u32 = 0x4353_4E53;
+/// Flash write granularity.
+pub const WORD_SIZE: usize = 4;
+/// Largest record we will ever write, header and padding included.
+pub const MAX_RECORD_SIZE: usize = RecordHeader::SIZE + CONFIG_SIZE;
+
+const CRC_CONFIG: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+fn checksum(version: u16, payload: &[u8]) -> u32 {
+    let mut digest = CRC_CONFIG.digest();
+    digest.update(&version.to_le_bytes());
+    digest.update(&(payload.len() as u16).to_le_bytes());
+    digest.update(payload);
+    digest.finalize()
+}
+
+/// Rounds `len` up to the next multiple of the flash word size.
+pub fn padded_len(len: usize) -> usize {
+    (len + WORD_SIZE - 1) / WORD_SIZE * WORD_SIZE
+}
+
+/// Returns true if `raw` starts with erased flash, meaning nothing was ever written there.
+pub fn is_erased(raw: &[u8]) -> bool {
+    raw.iter().take(WORD_SIZE).all(|b| *b == 0xFF)
+}
+
+/// Returns true if `raw` starts with the record magic.
+pub fn has_magic(raw: &[u8]) -> bool {
+    raw.get(..WORD_SIZE) == Some(&RECORD_MAGIC.to_le_bytes()[..])
+}
+
+/// Returns the padded size of the record at the start of `raw`, judging by its header alone. Used
+/// to skip over records that fail their CRC.
+pub fn peek_len(raw: &[u8]) -> Option<usize> {
+    let header_bytes: &[u8; RecordHeader::SIZE] = raw.get(..RecordHeader::SIZE)?.try_into().ok()?;
+    let header = RecordHeader::from_bytes(header_bytes);
+
+    match header.magic == RECORD_MAGIC && header.length as usize <= CONFIG_SIZE {
+        true => Some(padded_len(RecordHeader::SIZE + header.length as usize)),
+        false => None,
+    }
+}
+
+/// Serializes `config` into a framed record. Returns the number of bytes to be written, which is
+/// always a multiple of `WORD_SIZE`.
+pub fn encode(
+    config: &SensusConfig,
+    buf: &mut [u8; MAX_RECORD_SIZE],
+) -> Result<usize, ConfigError> {
+    let (header_buf, payload_buf) = buf.split_at_mut(RecordHeader::SIZE);
+    let length = postcard::to_slice(config, payload_buf)
+        .map_err(|_| ConfigError::SerializationError)?
+        .len();
+
+    let header = RecordHeader {
+        magic: RECORD_MAGIC,
+        version: CONFIG_VERSION,
+        length: length as u16,
+        crc: checksum(CONFIG_VERSION, &payload_buf[..length]),
+    };
+    header_buf.copy_from_slice(&header.to_bytes());
+
+    let record_len = RecordHeader::SIZE + length;
+    let padded = padded_len(record_len);
+    buf[record_len..padded].fill(0xFF);
+    Ok(padded)
+}
+
+/// Decodes the record found at the start of `raw`, upgrading it to the current schema if needed.
+/// Returns the config together with the padded size the record occupies in flash.
+pub fn decode(raw: &[u8]) -> Result<(SensusConfig, usize), ConfigError> {
+    let header_bytes: &[u8; RecordHeader::SIZE] = raw
+        .get(..RecordHeader::SIZE)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(ConfigError::CorruptRecord)?;
+    let header = RecordHeader::from_bytes(header_bytes);
+
+    if header.magic != RECORD_MAGIC {
+        return Err(ConfigError::CorruptRecord);
+    }
+
+    let length = header.length as usize;
+    let payload = raw
+        .get(RecordHeader::SIZE..RecordHeader::SIZE + length)
+        .ok_or(ConfigError::CorruptRecord)?;
+    if checksum(header.version, payload) != header.crc {
+        return Err(ConfigError::CorruptRecord);
+    }
+
+    let config = migrations::decode(header.version, payload)?;
+    Ok((config, padded_len(RecordHeader::SIZE + length)))
+}
+
+/// Decodes a config written by firmware predating the record format, i.e. a bare postcard-encoded
+/// `SensusConfig` without any header. There is no CRC to go by, so the result is sanity-checked
+/// with `SensusConfig::verify` instead. The calibration and sample period rules got stricter
+/// since, so values failing them are replaced or clamped rather than taken as a sign of corruption.
+pub fn decode_legacy(raw: &[u8]) -> Result<SensusConfig, ConfigError> {
+    let payload = &raw[..raw.len().min(CONFIG_SIZE)];
+    migrations::decode(LEGACY_VERSION, payload)?
+        .with_valid_calibration()
+        .with_valid_periods()
+        .verify()
+        .map_err(|_| ConfigError::CorruptRecord)
+}
@@ -4,54 +4,122 @@ use defmt::Format;
 use heapless::Vec;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Format, Clone)]
-pub enum ConfigError {
-    SerializationError,
-    InvalidSampleRate,
-    Flash(u8),
-}
+pub use sensus_protocol::config::{
+    CalibrationPoint, ConfigError, ConfigField, ConfigPayload, ConfigResponse, ConfigSections,
+    ConfigValue, DisplayableVec, ResetScope, SamplePeriod, Sensor, SensusConfigOld,
+    MAX_CALIBRATION_POINTS,
+};
 
-// TODO. Limit all values to 1 second minimum.
-#[repr(C)]
-#[derive(Serialize, Deserialize, Format, Clone, PartialEq)]
-pub struct SamplePeriod {
-    #[serde(with = "postcard::fixint::le")]
-    pub onboard_sdt_plugged_ms: u32,
-    #[serde(with = "postcard::fixint::le")]
-    pub probe_sdt_plugged_ms: u32,
-    #[serde(with = "postcard::fixint::le")]
-    pub onboard_sdt_battery_ms: u32,
-    #[serde(with = "postcard::fixint::le")]
-    pub probe_sdt_battery_ms: u32,
+/// Header prepended to every config record stored in flash. See `config_manager::record`.
+#[derive(Format, Clone, Copy, PartialEq)]
+pub struct RecordHeader {
+    pub magic: u32,
+    pub version: u16,
+    pub length: u16,
+    pub crc: u32,
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq)]
-pub struct DisplayableVec<T, const N: usize>(Vec<T, N>);
+impl RecordHeader {
+    pub const SIZE: usize = 12;
 
-impl<T, const N: usize> DisplayableVec<T, N> {
-    pub fn inner(self) -> Vec<T, N> {
-        self.0
+    pub fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut buf = [0u8; Self::SIZE];
+        buf[0..4].copy_from_slice(&self.magic.to_le_bytes());
+        buf[4..6].copy_from_slice(&self.version.to_le_bytes());
+        buf[6..8].copy_from_slice(&self.length.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.crc.to_le_bytes());
+        buf
     }
+
+    pub fn from_bytes(buf: &[u8; Self::SIZE]) -> Self {
+        RecordHeader {
+            magic: u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
+            version: u16::from_le_bytes([buf[4], buf[5]]),
+            length: u16::from_le_bytes([buf[6], buf[7]]),
+            crc: u32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]),
+        }
+    }
+}
+
+/// Header at the start of every committed page of the config journal. See `config_manager::journal`.
+#[derive(Format, Clone, Copy, PartialEq)]
+pub struct PageHeader {
+    pub magic: u32,
+    pub sequence: u32,
 }
 
-impl<T, const N: usize> Format for DisplayableVec<T, N>
-where
-    T: defmt::Format,
-{
-    fn format(&self, fmt: defmt::Formatter) {
-        defmt::write!(fmt, "{}", self.0.as_slice())
+impl PageHeader {
+    pub const SIZE: usize = 8;
+
+    pub fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut buf = [0u8; Self::SIZE];
+        buf[0..4].copy_from_slice(&self.magic.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.sequence.to_le_bytes());
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8; Self::SIZE]) -> Self {
+        PageHeader {
+            magic: u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
+            sequence: u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Format, Clone, PartialEq, PartialOrd, Ord, Eq)]
-struct CalibrationPoint {
-    frequency: u32,
-    percentage: u8,
+/// Sample period limits, in milliseconds. A sensor sampled less than once a day is better disabled.
+pub const SAMPLE_PERIOD_MIN_MS: u32 = 1000;
+pub const SAMPLE_PERIOD_MAX_MS: u32 = 86_400_000;
+/// Longest filter settling time, in milliseconds: a week.
+pub const SETTLING_MAX_MS: u32 = 604_800_000;
+/// Shortest serial timeouts, in milliseconds. The largest frame we accept takes about 6ms at
+/// 460800 baud, so anything shorter would lock the host out of the UART for good.
+pub const SERIAL_FRAME_TIMEOUT_MIN_MS: u32 = 10;
+pub const SERIAL_BYTE_TIMEOUT_MIN_MS: u32 = 3;
+/// Advertising interval limits imposed by the SoftDevice, in milliseconds.
+pub const ADV_INTERVAL_MIN_MS: u32 = 20;
+pub const ADV_INTERVAL_MAX_MS: u32 = 10240;
+/// TX power levels supported by the nRF52832 radio, in dBm.
+pub const TX_POWER_LEVELS_DBM: [i8; 9] = [-40, -20, -16, -12, -8, -4, 0, 3, 4];
+
+#[repr(C)]
+#[derive(Serialize, Deserialize, Format, Clone, PartialEq)]
+pub struct AdvertisingParams {
+    #[serde(with = "postcard::fixint::le")]
+    pub interval_plugged_ms: u32,
+    #[serde(with = "postcard::fixint::le")]
+    pub interval_battery_ms: u32,
+    pub tx_power_plugged_dbm: i8,
+    pub tx_power_battery_dbm: i8,
+}
+
+/// Time it takes each filtered quantity to reach 99% of a step change. The filter constants are
+/// derived from these and the active sample period.
+#[repr(C)]
+#[derive(Serialize, Deserialize, Format, Clone, PartialEq)]
+pub struct FilterSettling {
+    #[serde(with = "postcard::fixint::le")]
+    pub environment_settling_ms: u32,
+    #[serde(with = "postcard::fixint::le")]
+    pub battery_settling_ms: u32,
+    #[serde(with = "postcard::fixint::le")]
+    pub probe_settling_ms: u32,
+}
+
+/// How long the UART waits for the rest of a frame before discarding it.
+#[repr(C)]
+#[derive(Serialize, Deserialize, Format, Clone, PartialEq)]
+pub struct SerialParams {
+    /// Time from the first byte of a frame to its terminating 0x00.
+    #[serde(with = "postcard::fixint::le")]
+    pub frame_timeout_ms: u32,
+    /// Longest pause allowed between two bytes of the same frame.
+    #[serde(with = "postcard::fixint::le")]
+    pub byte_timeout_ms: u32,
 }
 
 #[derive(Serialize, Deserialize, Format, Clone, PartialEq)]
 pub struct ProbeCalibration {
-    points: DisplayableVec<CalibrationPoint, 10>,
+    points: DisplayableVec<CalibrationPoint, MAX_CALIBRATION_POINTS>,
 }
 
 impl ProbeCalibration {
@@ -66,31 +134,161 @@ impl ProbeCalibration {
         vec
     }
 
-    fn from_vec(probe_calibration: DisplayableVec<f32, 20>) -> ProbeCalibration {
+    /// Wraps points decoded from flash. They are neither sorted nor verified here, that's up to
+    /// `SensusConfig::with_valid_calibration`.
+    pub(super) fn from_points(points: Vec<CalibrationPoint, MAX_CALIBRATION_POINTS>) -> Self {
+        ProbeCalibration {
+            points: DisplayableVec(points),
+        }
+    }
+
+    /// Returns the calibration points, sorted by frequency.
+    pub fn points(&self) -> &[CalibrationPoint] {
+        self.points.0.as_slice()
+    }
+
+    /// Returns a copy of the calibration points, as sent to the host.
+    pub fn list(&self) -> DisplayableVec<CalibrationPoint, MAX_CALIBRATION_POINTS> {
+        self.points.clone()
+    }
+
+    /// Builds a calibration out of the flat `[f0, p0, f1, p1, ...]` representation used on the wire,
+    /// where percentages are given as fractions between 0 and 1.
+    fn try_from_vec(
+        probe_calibration: DisplayableVec<f32, 20>,
+    ) -> Result<ProbeCalibration, ConfigError> {
         let raw_points = probe_calibration.inner();
+        if raw_points.len() % 2 != 0 {
+            return Err(ConfigError::CalibrationMalformed);
+        }
 
-        // TODO: Sort by frequencies.
         let frequencies = raw_points.clone().into_iter().step_by(2);
         let percentages = raw_points.into_iter().skip(1).step_by(2);
 
-        // My actual calibration points
-        let mut points = Vec::<CalibrationPoint, 10>::new();
+        let mut calibration = ProbeCalibration {
+            points: DisplayableVec(Vec::new()),
+        };
 
         for (f, p) in zip(frequencies, percentages) {
-            let point = CalibrationPoint {
+            // Casting would quietly saturate anything else.
+            if !(0.0..u32::MAX as f32).contains(&f) {
+                return Err(ConfigError::CalibrationMalformed);
+            }
+            if !(0.0..=1.0).contains(&p) {
+                return Err(ConfigError::CalibrationPercentageOutOfRange);
+            }
+            calibration.insert(CalibrationPoint {
                 frequency: f as u32,
-                percentage: (p * 100.0) as u8,
-            };
+                percentage: (p * 100.0 + 0.5) as u8, // Round instead of truncating.
+            })?;
+        }
+
+        calibration.verify()?;
+        Ok(calibration)
+    }
 
-            match points.binary_search(&point) {
-                Ok(_pos) => {} // element already in vector @ `pos`
-                Err(pos) => defmt::unwrap!(points.insert(pos, point)),
-            };
+    /// Inserts a point while keeping the points sorted by frequency. Doesn't check monotonicity.
+    fn insert(&mut self, point: CalibrationPoint) -> Result<(), ConfigError> {
+        if point.percentage > 100 {
+            return Err(ConfigError::CalibrationPercentageOutOfRange);
         }
 
-        ProbeCalibration {
-            points: DisplayableVec(points),
+        let points = &mut self.points.0;
+        match points.binary_search_by_key(&point.frequency, |p| p.frequency) {
+            // Two points with the same frequency can't both be right.
+            Ok(_pos) => Err(ConfigError::CalibrationNotMonotonic),
+            Err(pos) => points
+                .insert(pos, point)
+                .map_err(|_| ConfigError::CalibrationFull),
+        }
+    }
+
+    /// Adds a new calibration point. Returns the index it was inserted at.
+    pub fn add(&mut self, point: CalibrationPoint) -> Result<u8, ConfigError> {
+        let frequency = point.frequency;
+        self.insert(point)?;
+        self.verify()?;
+
+        let index = self.points().iter().position(|p| p.frequency == frequency);
+        Ok(defmt::unwrap!(index) as u8)
+    }
+
+    /// Removes the calibration point with the given index.
+    pub fn remove(&mut self, index: u8) -> Result<(), ConfigError> {
+        let points = &mut self.points.0;
+        if index as usize >= points.len() {
+            return Err(ConfigError::CalibrationIndexOutOfRange(index));
+        }
+
+        points.remove(index as usize);
+        self.verify()
+    }
+
+    /// Replaces the calibration point with the given index.
+    pub fn replace(&mut self, index: u8, point: CalibrationPoint) -> Result<(), ConfigError> {
+        let points = &mut self.points.0;
+        if index as usize >= points.len() {
+            return Err(ConfigError::CalibrationIndexOutOfRange(index));
         }
+
+        points.remove(index as usize);
+        self.insert(point)?;
+        self.verify()
+    }
+
+    /// Checks that the calibration has at least two points, valid percentages and that it maps
+    /// frequencies to moisture in a strictly monotonic way.
+    pub fn verify(&self) -> Result<(), ConfigError> {
+        let points = self.points();
+        if points.len() < 2 {
+            return Err(ConfigError::CalibrationTooFewPoints);
+        }
+        if points.iter().any(|p| p.percentage > 100) {
+            return Err(ConfigError::CalibrationPercentageOutOfRange);
+        }
+
+        let frequencies_increasing = points.windows(2).all(|w| w[0].frequency < w[1].frequency);
+        let moisture_increasing = points.windows(2).all(|w| w[0].percentage < w[1].percentage);
+        let moisture_decreasing = points.windows(2).all(|w| w[0].percentage > w[1].percentage);
+        if !frequencies_increasing || !(moisture_increasing || moisture_decreasing) {
+            return Err(ConfigError::CalibrationNotMonotonic);
+        }
+
+        Ok(())
+    }
+}
+
+#[repr(C)]
+#[derive(Serialize, Deserialize, Format, Clone, Copy, PartialEq)]
+pub struct EnabledSensors {
+    pub probe: bool,
+    pub opt3001: bool,
+    pub shtc3: bool,
+    pub battery: bool,
+}
+
+impl EnabledSensors {
+    pub fn is_enabled(&self, sensor: Sensor) -> bool {
+        match sensor {
+            Sensor::Probe => self.probe,
+            Sensor::Opt3001 => self.opt3001,
+            Sensor::Shtc3 => self.shtc3,
+            Sensor::Battery => self.battery,
+        }
+    }
+
+    pub fn set_enabled(&mut self, sensor: Sensor, enabled: bool) {
+        match sensor {
+            Sensor::Probe => self.probe = enabled,
+            Sensor::Opt3001 => self.opt3001 = enabled,
+            Sensor::Shtc3 => self.shtc3 = enabled,
+            Sensor::Battery => self.battery = enabled,
+        }
+    }
+
+    /// True if at least one of the onboard sensors is enabled.
+    pub fn any_onboard(&self) -> bool {
+        self.opt3001 || self.shtc3 || self.battery
     }
 }
 
@@ -102,26 +300,27 @@ pub struct SensusConfig {
     #[defmt(Display2Format)] // TODO. Remove and replace with Format implementation
     pub name: heapless::String<29>,
     pub probe_calibration: ProbeCalibration,
+    pub sensors: EnabledSensors,
+    pub advertising: AdvertisingParams,
+    pub filter: FilterSettling,
+    pub serial: SerialParams,
 }
 
-#[repr(C)]
-#[derive(Serialize, Deserialize, Format, Clone, PartialEq)]
-pub struct SensusConfigOld {
-    pub sampling_period: SamplePeriod,
-    #[defmt(Display2Format)] // TODO. Remove and replace with Format implementation
-    pub name: heapless::String<29>,
-    pub probe_calibration: DisplayableVec<f32, 20>,
-}
+impl TryFrom<SensusConfigOld> for SensusConfig {
+    type Error = ConfigError;
 
-impl From<SensusConfigOld> for SensusConfig {
-    fn from(value: SensusConfigOld) -> Self {
-        let probecal = ProbeCalibration::from_vec(value.probe_calibration);
+    fn try_from(value: SensusConfigOld) -> Result<Self, Self::Error> {
+        let probecal = ProbeCalibration::try_from_vec(value.probe_calibration)?;
 
-        SensusConfig {
+        Ok(SensusConfig {
             sampling_period: value.sampling_period,
             name: value.name,
             probe_calibration: probecal,
-        }
+            sensors: Default::default(),
+            advertising: Default::default(),
+            filter: Default::default(),
+            serial: Default::default(),
+        })
     }
 }
 
@@ -135,29 +334,100 @@ impl From<SensusConfig> for SensusConfigOld {
     }
 }
 
-#[derive(Serialize, Deserialize, Format, Clone)]
-pub enum ConfigPayload {
-    ConfigGet,
-    ConfigSet(SensusConfigOld),
+//
+// Implementations
+//
+
+impl Default for FilterSettling {
+    /// Matches the smoothing the firmware used to hard-code at the default 30s battery sample
+    /// period: alpha = 0.329 for the environment and probe, alpha = 0.181 for the battery.
+    fn default() -> Self {
+        Self {
+            environment_settling_ms: 300_000,
+            battery_settling_ms: 600_000,
+            probe_settling_ms: 300_000,
+        }
+    }
 }
 
-#[derive(Serialize, Format, Clone)]
-pub enum ConfigResponse {
-    GetConfig(SensusConfigOld),
-    SetConfig, // Set config successfully.
+impl FilterSettling {
+    /// Checks that every settling time either disables filtering or lies within sane limits.
+    pub fn verify(&self) -> Result<(), ConfigError> {
+        let valid = |settling_ms: u32| {
+            settling_ms == 0 || (SAMPLE_PERIOD_MIN_MS..=SETTLING_MAX_MS).contains(&settling_ms)
+        };
+        if !valid(self.environment_settling_ms)
+            || !valid(self.battery_settling_ms)
+            || !valid(self.probe_settling_ms)
+        {
+            return Err(ConfigError::InvalidSettlingTime);
+        }
+
+        Ok(())
+    }
 }
 
-//
-// Implementations
-//
+impl Default for SerialParams {
+    /// A 256 byte frame takes about 6ms at 460800 baud, so these only trigger when the host is gone.
+    fn default() -> Self {
+        Self {
+            frame_timeout_ms: 500,
+            byte_timeout_ms: 50,
+        }
+    }
+}
 
-impl Default for SamplePeriod {
+impl SerialParams {
+    pub fn verify(&self) -> Result<(), ConfigError> {
+        if self.frame_timeout_ms < SERIAL_FRAME_TIMEOUT_MIN_MS
+            || self.byte_timeout_ms < SERIAL_BYTE_TIMEOUT_MIN_MS
+            || self.byte_timeout_ms > self.frame_timeout_ms
+        {
+            return Err(ConfigError::InvalidSerialTimeout);
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for AdvertisingParams {
     fn default() -> Self {
         Self {
-            onboard_sdt_plugged_ms: 10000,
-            probe_sdt_plugged_ms: 10000,
-            onboard_sdt_battery_ms: 30000,
-            probe_sdt_battery_ms: 30000,
+            interval_plugged_ms: 1000,
+            interval_battery_ms: 1000,
+            tx_power_plugged_dbm: 4,
+            tx_power_battery_dbm: 4,
+        }
+    }
+}
+
+impl AdvertisingParams {
+    /// Checks the intervals and TX powers against what the SoftDevice accepts.
+    pub fn verify(&self) -> Result<(), ConfigError> {
+        let interval_range = ADV_INTERVAL_MIN_MS..=ADV_INTERVAL_MAX_MS;
+        if !interval_range.contains(&self.interval_plugged_ms)
+            || !interval_range.contains(&self.interval_battery_ms)
+        {
+            return Err(ConfigError::InvalidAdvInterval);
+        }
+
+        if !TX_POWER_LEVELS_DBM.contains(&self.tx_power_plugged_dbm)
+            || !TX_POWER_LEVELS_DBM.contains(&self.tx_power_battery_dbm)
+        {
+            return Err(ConfigError::InvalidTxPower);
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for EnabledSensors {
+    fn default() -> Self {
+        Self {
+            probe: true,
+            opt3001: true,
+            shtc3: true,
+            battery: true,
         }
     }
 }
@@ -183,20 +453,298 @@ impl Default for SensusConfig {
             sampling_period: Default::default(),
             name: defmt::unwrap!(heapless::String::from_str("Sensus")),
             probe_calibration: probecal,
+            sensors: Default::default(),
+            advertising: Default::default(),
+            filter: Default::default(),
+            serial: Default::default(),
         }
     }
 }
 
 impl SensusConfig {
+    /// Returns the sections that differ between two configs.
+    pub fn diff(&self, other: &SensusConfig) -> ConfigSections {
+        let mut sections = ConfigSections::NONE;
+        let mut mark = |changed: bool, section: ConfigSections| {
+            if changed {
+                sections = sections | section;
+            }
+        };
+
+        mark(
+            self.sampling_period != other.sampling_period,
+            ConfigSections::SAMPLING_PERIOD,
+        );
+        mark(self.name != other.name, ConfigSections::NAME);
+        mark(
+            self.probe_calibration != other.probe_calibration,
+            ConfigSections::CALIBRATION,
+        );
+        mark(self.sensors != other.sensors, ConfigSections::SENSORS);
+        mark(
+            self.advertising != other.advertising,
+            ConfigSections::ADVERTISING,
+        );
+        mark(self.filter != other.filter, ConfigSections::FILTER);
+        mark(self.serial != other.serial, ConfigSections::SERIAL);
+
+        sections
+    }
+
+    /// Returns the current value of a single field.
+    pub fn get_field(&self, field: ConfigField) -> Result<ConfigValue, ConfigError> {
+        let period = &self.sampling_period;
+        let value = match field {
+            ConfigField::OnboardSdtPluggedMs => {
+                ConfigValue::OnboardSdtPluggedMs(period.onboard_sdt_plugged_ms)
+            }
+            ConfigField::ProbeSdtPluggedMs => {
+                ConfigValue::ProbeSdtPluggedMs(period.probe_sdt_plugged_ms)
+            }
+            ConfigField::OnboardSdtBatteryMs => {
+                ConfigValue::OnboardSdtBatteryMs(period.onboard_sdt_battery_ms)
+            }
+            ConfigField::ProbeSdtBatteryMs => {
+                ConfigValue::ProbeSdtBatteryMs(period.probe_sdt_battery_ms)
+            }
+            ConfigField::Name => ConfigValue::Name(self.name.clone()),
+            ConfigField::CalibrationPoint(index) => {
+                let point = self
+                    .probe_calibration
+                    .points()
+                    .get(index as usize)
+                    .ok_or(ConfigError::CalibrationIndexOutOfRange(index))?;
+                ConfigValue::CalibrationPoint(index, point.clone())
+            }
+            ConfigField::SensorEnabled(sensor) => {
+                ConfigValue::SensorEnabled(sensor, self.sensors.is_enabled(sensor))
+            }
+            ConfigField::AdvIntervalPluggedMs => {
+                ConfigValue::AdvIntervalPluggedMs(self.advertising.interval_plugged_ms)
+            }
+            ConfigField::AdvIntervalBatteryMs => {
+                ConfigValue::AdvIntervalBatteryMs(self.advertising.interval_battery_ms)
+            }
+            ConfigField::TxPowerPluggedDbm => {
+                ConfigValue::TxPowerPluggedDbm(self.advertising.tx_power_plugged_dbm)
+            }
+            ConfigField::TxPowerBatteryDbm => {
+                ConfigValue::TxPowerBatteryDbm(self.advertising.tx_power_battery_dbm)
+            }
+            ConfigField::EnvironmentSettlingMs => {
+                ConfigValue::EnvironmentSettlingMs(self.filter.environment_settling_ms)
+            }
+            ConfigField::BatterySettlingMs => {
+                ConfigValue::BatterySettlingMs(self.filter.battery_settling_ms)
+            }
+            ConfigField::ProbeSettlingMs => {
+                ConfigValue::ProbeSettlingMs(self.filter.probe_settling_ms)
+            }
+            ConfigField::SerialFrameTimeoutMs => {
+                ConfigValue::SerialFrameTimeoutMs(self.serial.frame_timeout_ms)
+            }
+            ConfigField::SerialByteTimeoutMs => {
+                ConfigValue::SerialByteTimeoutMs(self.serial.byte_timeout_ms)
+            }
+        };
+
+        Ok(value)
+    }
+
+    /// Replaces a single field, validating only the new value.
+    pub fn patch(mut self, value: ConfigValue) -> Result<Self, ConfigError> {
+        let period_range = SAMPLE_PERIOD_MIN_MS..=SAMPLE_PERIOD_MAX_MS;
+        let check_period = |period: u32| match period_range.contains(&period) {
+            true => Ok(period),
+            false => Err(ConfigError::InvalidSampleRate),
+        };
+
+        match value {
+            ConfigValue::OnboardSdtPluggedMs(period) => {
+                self.sampling_period.onboard_sdt_plugged_ms = check_period(period)?;
+            }
+            ConfigValue::ProbeSdtPluggedMs(period) => {
+                self.sampling_period.probe_sdt_plugged_ms = check_period(period)?;
+            }
+            ConfigValue::OnboardSdtBatteryMs(period) => {
+                self.sampling_period.onboard_sdt_battery_ms = check_period(period)?;
+            }
+            ConfigValue::ProbeSdtBatteryMs(period) => {
+                self.sampling_period.probe_sdt_battery_ms = check_period(period)?;
+            }
+            ConfigValue::Name(name) => {
+                if name.is_empty() {
+                    return Err(ConfigError::InvalidName);
+                }
+                self.name = name;
+            }
+            ConfigValue::CalibrationPoint(index, point) => {
+                self.probe_calibration.replace(index, point)?;
+            }
+            ConfigValue::SensorEnabled(sensor, enabled) => {
+                self.sensors.set_enabled(sensor, enabled);
+            }
+            ConfigValue::AdvIntervalPluggedMs(interval) => {
+                self.advertising.interval_plugged_ms = interval;
+                self.advertising.verify()?;
+            }
+            ConfigValue::AdvIntervalBatteryMs(interval) => {
+                self.advertising.interval_battery_ms = interval;
+                self.advertising.verify()?;
+            }
+            ConfigValue::TxPowerPluggedDbm(tx_power) => {
+                self.advertising.tx_power_plugged_dbm = tx_power;
+                self.advertising.verify()?;
+            }
+            ConfigValue::TxPowerBatteryDbm(tx_power) => {
+                self.advertising.tx_power_battery_dbm = tx_power;
+                self.advertising.verify()?;
+            }
+            ConfigValue::EnvironmentSettlingMs(settling) => {
+                self.filter.environment_settling_ms = settling;
+                self.filter.verify()?;
+            }
+            ConfigValue::BatterySettlingMs(settling) => {
+                self.filter.battery_settling_ms = settling;
+                self.filter.verify()?;
+            }
+            ConfigValue::ProbeSettlingMs(settling) => {
+                self.filter.probe_settling_ms = settling;
+                self.filter.verify()?;
+            }
+            ConfigValue::SerialFrameTimeoutMs(timeout) => {
+                self.serial.frame_timeout_ms = timeout;
+                self.serial.verify()?;
+            }
+            ConfigValue::SerialByteTimeoutMs(timeout) => {
+                self.serial.byte_timeout_ms = timeout;
+                self.serial.verify()?;
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Applies a config received in the full-struct wire format. Fields that the wire format
+    /// doesn't carry keep their current value.
+    pub fn apply_old(self, value: SensusConfigOld) -> Result<Self, ConfigError> {
+        Ok(SensusConfig {
+            sensors: self.sensors,
+            advertising: self.advertising,
+            filter: self.filter,
+            serial: self.serial,
+            ..value.try_into()?
+        })
+    }
+
+    /// Restores the given part of the config to its default value.
+    pub fn reset(self, scope: ResetScope) -> Self {
+        let defaults = SensusConfig::default();
+        match scope {
+            ResetScope::All => defaults,
+            ResetScope::Calibration => SensusConfig {
+                probe_calibration: defaults.probe_calibration,
+                ..self
+            },
+            ResetScope::SamplingPeriods => SensusConfig {
+                sampling_period: defaults.sampling_period,
+                ..self
+            },
+        }
+    }
+
+    /// Replaces a stored calibration that fails `ProbeCalibration::verify` with the default one.
+    /// Older firmware accepted calibrations the current rules don't, and losing the rest of the
+    /// config over that would be worse. New calibrations still have to pass.
+    pub fn with_valid_calibration(self) -> Self {
+        match self.probe_calibration.verify() {
+            Ok(_) => self,
+            Err(err) => {
+                defmt::warn!("Invalid stored calibration: {:?}. Using the default.", err);
+                self.reset(ResetScope::Calibration)
+            }
+        }
+    }
+
+    /// Clamps stored sample periods and filter settling times into the limits `verify` enforces.
+    /// Older firmware accepted periods of over a day, and dropping the whole config over that
+    /// would lose the calibration along with it. New values still have to pass.
+    pub fn with_valid_periods(mut self) -> Self {
+        let clamp_period = |period: &mut u32| {
+            *period = (*period).clamp(SAMPLE_PERIOD_MIN_MS, SAMPLE_PERIOD_MAX_MS);
+        };
+        // Zero disables filtering, so it's kept.
+        let clamp_settling = |settling: &mut u32| {
+            if *settling != 0 {
+                *settling = (*settling).clamp(SAMPLE_PERIOD_MIN_MS, SETTLING_MAX_MS);
+            }
+        };
+
+        let original = self.clone();
+        let period = &mut self.sampling_period;
+        clamp_period(&mut period.onboard_sdt_plugged_ms);
+        clamp_period(&mut period.probe_sdt_plugged_ms);
+        clamp_period(&mut period.onboard_sdt_battery_ms);
+        clamp_period(&mut period.probe_sdt_battery_ms);
+        let filter = &mut self.filter;
+        clamp_settling(&mut filter.environment_settling_ms);
+        clamp_settling(&mut filter.battery_settling_ms);
+        clamp_settling(&mut filter.probe_settling_ms);
+
+        if self != original {
+            defmt::warn!("Stored sample periods or settling times out of range. Clamped them.");
+        }
+        self
+    }
+
     pub fn verify(self) -> Result<Self, ConfigError> {
-        if self.sampling_period.onboard_sdt_battery_ms < 1000
-            || self.sampling_period.onboard_sdt_plugged_ms < 1000
-            || self.sampling_period.probe_sdt_battery_ms < 1000
-            || self.sampling_period.probe_sdt_plugged_ms < 1000
+        let period_range = SAMPLE_PERIOD_MIN_MS..=SAMPLE_PERIOD_MAX_MS;
+        let period = &self.sampling_period;
+        if !period_range.contains(&period.onboard_sdt_battery_ms)
+            || !period_range.contains(&period.onboard_sdt_plugged_ms)
+            || !period_range.contains(&period.probe_sdt_battery_ms)
+            || !period_range.contains(&period.probe_sdt_plugged_ms)
         {
             return Err(ConfigError::InvalidSampleRate);
         }
+        self.advertising.verify()?;
+        self.filter.verify()?;
+        self.probe_calibration.verify()?;
+        self.serial.verify()?;
 
         Ok(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calibration(raw: &[f32]) -> Result<ProbeCalibration, ConfigError> {
+        ProbeCalibration::try_from_vec(DisplayableVec(Vec::from_slice(raw).unwrap()))
+    }
+
+    #[test]
+    fn calibration_from_vec_takes_pairs() {
+        let calibration = calibration(&[100000.0, 1.0, 1700000.0, 0.0]).unwrap();
+        assert!(calibration == SensusConfig::default().probe_calibration);
+    }
+
+    #[test]
+    fn calibration_from_vec_rejects_unpaired_values() {
+        assert!(matches!(
+            calibration(&[100000.0, 1.0, 1700000.0, 0.0, 2000000.0]),
+            Err(ConfigError::CalibrationMalformed)
+        ));
+    }
+
+    #[test]
+    fn calibration_from_vec_rejects_invalid_frequencies() {
+        for frequency in [-1.0, f32::NAN, f32::INFINITY, f32::NEG_INFINITY, 1e10] {
+            assert!(matches!(
+                calibration(&[100000.0, 1.0, frequency, 0.0]),
+                Err(ConfigError::CalibrationMalformed)
+            ));
+        }
+    }
+}
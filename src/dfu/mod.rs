@@ -2,11 +2,48 @@ pub mod types;
 
 mod state_machine;
 
+use core::sync::atomic::{
+    AtomicBool,
+    Ordering::{Relaxed, SeqCst},
+};
+
+use embassy_boot_nrf::{AlignedBuffer, FirmwareUpdater};
 use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, signal::Signal};
-use types::DfuPayload;
+use types::{DfuError, DfuHeader, DfuPayload, BLOCK_SIZE};
+
+use crate::comm_manager::transport::Origin;
+use crate::FLASH_DRIVER;
+
+/// Used to send data to the DFU state machine to process, along with the request that carried it.
+static PAYLOAD_PROVIDER: Signal<ThreadModeRawMutex, (Origin, DfuPayload)> = Signal::new();
+/// Largest image we accept: the size of the active partition, FLASH in memory.x. The DFU partition
+/// right after it is only one page larger, with CONFIG and the bootloader behind that.
+pub const MAX_IMAGE_SIZE: usize = 160 * 1024;
+
+/// Set once the running firmware has been confirmed to the bootloader.
+static MARKED_BOOTED: AtomicBool = AtomicBool::new(false);
+/// Set while an `UpdateGuard` exists, meaning some transport is writing the DFU partition.
+static UPDATE_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+/// Ownership of the DFU partition. Only one transport gets to write an image at a time, the others
+/// are turned away with `DfuError::StateMachineError` until the guard is dropped.
+pub struct UpdateGuard(());
+
+impl UpdateGuard {
+    /// Takes ownership of the DFU partition, unless some other update holds it.
+    pub fn acquire() -> Option<Self> {
+        match UPDATE_IN_PROGRESS.compare_exchange(false, true, SeqCst, SeqCst) {
+            Ok(_) => Some(UpdateGuard(())),
+            Err(_) => None,
+        }
+    }
+}
 
-/// Used to send data to the DFU state machine to process.
-static PAYLOAD_PROVIDER: Signal<ThreadModeRawMutex, DfuPayload> = Signal::new();
+impl Drop for UpdateGuard {
+    fn drop(&mut self) {
+        UPDATE_IN_PROGRESS.store(false, SeqCst);
+    }
+}
 
 #[embassy_executor::task]
 pub async fn dfu_task() {
@@ -18,6 +55,43 @@ pub async fn dfu_task() {
 ///
 /// This function is basically the public interface to our DFU mechanism! This is the only thing
 /// we need to run in order to do DFU.
-pub async fn process_payload(payload: DfuPayload) {
-    PAYLOAD_PROVIDER.signal(payload);
+pub async fn process_payload(origin: Origin, payload: DfuPayload) {
+    PAYLOAD_PROVIDER.signal((origin, payload));
+}
+
+/// Checks the size announced by a DFU header before anything gets written. The image has to fit
+/// the active partition.
+pub fn check_image_size(header: &DfuHeader) -> Result<(), DfuError> {
+    match header.binary_size as usize {
+        1..=MAX_IMAGE_SIZE => Ok(()),
+        _ => Err(DfuError::InvalidImageSize),
+    }
+}
+
+/// Like `check_image_size`, but also checks that the image is split into `BLOCK_SIZE` blocks.
+pub fn check_header(header: &DfuHeader) -> Result<(), DfuError> {
+    check_image_size(header)?;
+    let binary_size = header.binary_size as usize;
+    match header.no_blocks as usize == (binary_size + BLOCK_SIZE - 1) / BLOCK_SIZE {
+        true => Ok(()),
+        false => Err(DfuError::InvalidImageSize),
+    }
+}
+
+/// Tells the bootloader that the running firmware works, so it doesn't roll back to the previous
+/// one on the next reset. Only touches the flash the first time around.
+pub async fn mark_booted() {
+    if MARKED_BOOTED.load(Relaxed) {
+        return;
+    }
+    let mut updater = FirmwareUpdater::default();
+    let mut magic = AlignedBuffer([0u8; 4]);
+    let mut f = FLASH_DRIVER.lock().await;
+    let flash_ref = defmt::unwrap!(f.as_mut());
+    updater
+        .mark_booted(flash_ref, magic.as_mut())
+        .await
+        .unwrap(); // Does not implement Format
+    MARKED_BOOTED.store(true, Relaxed);
+    defmt::info!("DFU WAS SUCCESSFUL.");
 }
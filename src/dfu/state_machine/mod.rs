@@ -1,22 +1,28 @@
 pub mod types;
 
+use core::str::FromStr;
+
 use defmt::error;
 use defmt::info;
 use defmt::warn;
 use embassy_boot_nrf::AlignedBuffer;
 use embassy_boot_nrf::FirmwareUpdater;
-use embassy_sync::pubsub::DynPublisher;
 use embassy_time::with_timeout;
 use embassy_time::Duration;
 use embassy_time::Timer;
+use heapless::String;
 
 use super::types::DfuError;
 use super::types::DfuPayload;
+use super::types::DfuResponse;
 use super::types::Page;
 
-use crate::comm_manager::types::CommResponse;
-use crate::comm_manager::types::DfuResponse;
-use crate::globals::TX_BUS;
+use crate::ble::CONN_INTERVAL_MAX_MS;
+use crate::ble::CONN_SLAVE_LATENCY;
+use crate::comm_manager::transport::Origin;
+use crate::comm_manager::transport::Transport;
+use crate::comm_manager::types::ResponseTypeErr;
+use crate::comm_manager::types::ResponseTypeOk;
 use crate::FIRMWARE_VERSION;
 use crate::FLASH_DRIVER;
 
@@ -27,9 +33,24 @@ use super::PAYLOAD_PROVIDER;
 
 const RETRY_COUNT: usize = 3;
 
+/// How long to wait for a requested block before asking for it again. Over BLE, the request goes
+/// out at the next connection event and the block comes back at the one after at the latest. Allow
+/// for twice that at the longest interval we ask centrals for, plus some time for the central.
+fn block_timeout(origin: Option<Origin>) -> Duration {
+    match origin.map(|origin| origin.transport) {
+        Some(Transport::Ble) | Some(Transport::BleDfu) => {
+            let event_ms = CONN_INTERVAL_MAX_MS * (CONN_SLAVE_LATENCY as u32 + 1);
+            Duration::from_millis((4 * event_ms + 100) as u64)
+        }
+        _ => Duration::from_millis(100),
+    }
+}
+
 impl DfuStateMachine {
     fn new() -> Self {
         DfuStateMachine {
+            origin: None,
+            update: None,
             current_block: 0,
             total_no_blocks: 0,
             binary_size: 0,
@@ -38,20 +59,16 @@ impl DfuStateMachine {
     }
 }
 
-async fn send_response_ok(queue: &DynPublisher<'_, CommResponse>, response: DfuResponse) {
-    queue
-        .publish(crate::comm_manager::types::CommResponse::Ok(
-            crate::comm_manager::types::ResponseTypeOk::Dfu(response),
-        ))
-        .await;
+fn send_response_ok(origin: Option<Origin>, response: DfuResponse) {
+    if let Some(origin) = origin {
+        origin.ok(ResponseTypeOk::Dfu(response));
+    }
 }
 
-async fn send_response_err(queue: &DynPublisher<'_, CommResponse>, response: DfuError) {
-    queue
-        .publish(crate::comm_manager::types::CommResponse::Err(
-            crate::comm_manager::types::ResponseTypeErr::Dfu(response),
-        ))
-        .await;
+fn send_response_err(origin: Option<Origin>, response: DfuError) {
+    if let Some(origin) = origin {
+        origin.err(ResponseTypeErr::Dfu(response));
+    }
 }
 
 /// Runs the DFU State Machine in an infinite loop.
@@ -60,17 +77,26 @@ pub async fn run() -> ! {
     let mut updater = FirmwareUpdater::default();
     let mut page = Page::new();
     let mut retry_counter = 0;
-    let data_tx = TX_BUS
-        .dyn_publisher()
-        .expect("Failed to acquire publisher.");
     loop {
         match sm.state {
             DfuSmState::Waiting => {
-                match PAYLOAD_PROVIDER.wait().await {
+                let (origin, payload) = PAYLOAD_PROVIDER.wait().await;
+                sm.origin = Some(origin);
+                match payload {
                     DfuPayload::StartDfu(header) => {
                         info!("Got the following DFU Header:");
                         info!("  binary size: {:#04x}", header.binary_size);
                         info!("  no_of_blocks: {:#04}", header.no_blocks);
+                        if let Err(e) = super::check_header(&header) {
+                            sm.state = DfuSmState::Error(e);
+                            continue;
+                        }
+                        sm.update = super::UpdateGuard::acquire();
+                        if sm.update.is_none() {
+                            warn!("Another DFU is in progress.");
+                            sm.state = DfuSmState::Error(DfuError::StateMachineError);
+                            continue;
+                        }
                         // Reset the global page buffer when receiving a new start-of-dfu.
                         page.reset();
 
@@ -81,8 +107,12 @@ pub async fn run() -> ! {
                     }
                     DfuPayload::RequestFwVersion => {
                         sm = DfuStateMachine::new();
-                        send_response_ok(&data_tx, DfuResponse::FirmwareVersion(FIRMWARE_VERSION))
-                            .await;
+                        send_response_ok(
+                            Some(origin),
+                            DfuResponse::FirmwareVersion(defmt::unwrap!(String::from_str(
+                                FIRMWARE_VERSION
+                            ))),
+                        );
                     }
                     _ => {
                         sm.state = DfuSmState::Error(DfuError::StateMachineError);
@@ -91,21 +121,41 @@ pub async fn run() -> ! {
             }
             DfuSmState::RequestBlock => {
                 // This state times out after three attempts to request a block.
-                let res = with_timeout(Duration::from_millis(100), async {
+                let res = with_timeout(block_timeout(sm.origin), async {
                     send_response_ok(
-                        &data_tx,
+                        sm.origin,
                         DfuResponse::RequestBlock(sm.current_block.to_le_bytes()),
-                    )
-                    .await;
-                    if let DfuPayload::Block(block) = PAYLOAD_PROVIDER.wait().await {
-                        retry_counter = 0;
-                        sm.state = DfuSmState::ProcessBlock(block);
-                    };
+                    );
+                    loop {
+                        let (origin, payload) = PAYLOAD_PROVIDER.wait().await;
+                        let transport = sm.origin.map(|origin| origin.transport);
+                        match payload {
+                            DfuPayload::Block(block)
+                                if Some(origin.transport) == transport
+                                    && block.block_idx == sm.current_block =>
+                            {
+                                return (origin, block);
+                            }
+                            // Someone else trying to update at the same time.
+                            _ if Some(origin.transport) != transport => {
+                                warn!("Another DFU is in progress, rejecting {:?}.", origin);
+                                send_response_err(Some(origin), DfuError::StateMachineError);
+                            }
+                            // A late answer to a request we already repeated. Taking it would
+                            // corrupt the image.
+                            _ => warn!("Dropping unexpected DFU payload from {:?}.", origin),
+                        }
+                    }
                 })
                 .await;
 
-                if res.is_err() {
-                    retry_counter += 1;
+                match res {
+                    Ok((origin, block)) => {
+                        sm.origin = Some(origin);
+                        retry_counter = 0;
+                        sm.state = DfuSmState::ProcessBlock(block);
+                    }
+                    Err(_) => retry_counter += 1,
                 }
 
                 if retry_counter >= RETRY_COUNT {
@@ -118,14 +168,24 @@ pub async fn run() -> ! {
                     .extend_from_slice(&block.data)
                     .expect("Page full. Is the block size a divisor of 4096?");
 
+                // Images don't have to end on a page boundary. Pad the rest like erased flash, so
+                // the tail gets written before the image is marked for the bootloader.
+                let last_block = sm.current_block + 1 == sm.total_no_blocks;
+                if last_block && page.length() > 0 {
+                    defmt::unwrap!(page.data.resize(page.data.capacity(), 0xFF));
+                }
+
                 if page.is_full() {
                     let mut f = FLASH_DRIVER.lock().await;
                     let flash_ref = defmt::unwrap!(f.as_mut());
                     // Flashes the filled page.
-                    updater
+                    let written = updater
                         .write_firmware(page.offset, page.data.as_slice(), flash_ref, page.length())
-                        .await
-                        .unwrap();
+                        .await;
+                    if written.is_err() {
+                        sm.state = DfuSmState::Error(DfuError::FlashError);
+                        continue;
+                    }
 
                     // Increments offset with 4096 and clears data.
                     page.data.clear();
@@ -140,18 +200,21 @@ pub async fn run() -> ! {
                 }
             }
             DfuSmState::Done => {
-                send_response_ok(&data_tx, DfuResponse::DfuDone).await;
-                // Will cause a reset.
-                info!("DFU Done! Resetting...");
-                Timer::after(Duration::from_secs(1)).await;
-                // Mark the firmware as updated and reset!
+                // Mark the firmware as updated, so the bootloader swaps it in on the reset.
                 let mut f = FLASH_DRIVER.lock().await;
                 let flash_ref = defmt::unwrap!(f.as_mut());
                 let mut magic = AlignedBuffer([0u8; 4]);
-                updater
-                    .mark_updated(flash_ref, magic.as_mut())
-                    .await
-                    .unwrap();
+                let marked = updater.mark_updated(flash_ref, magic.as_mut()).await;
+                drop(f);
+                if marked.is_err() {
+                    sm.state = DfuSmState::Error(DfuError::FlashError);
+                    continue;
+                }
+
+                send_response_ok(sm.origin, DfuResponse::DfuDone);
+                // Will cause a reset.
+                info!("DFU Done! Resetting...");
+                Timer::after(Duration::from_secs(1)).await;
                 // Reset microcontroller.
                 cortex_m::peripheral::SCB::sys_reset();
             }
@@ -161,8 +224,10 @@ pub async fn run() -> ! {
                         error!("DFU State Machine error. Maybe counter not ok?")
                     }
                     DfuError::TimeoutError => warn!("DFU Timeout. Resetting state machine."),
+                    DfuError::InvalidImageSize => warn!("DFU image doesn't fit the flash."),
+                    DfuError::FlashError => error!("DFU failed to write the flash."),
                 }
-                send_response_err(&data_tx, e).await;
+                send_response_err(sm.origin, e);
                 sm = DfuStateMachine::new();
             }
         }
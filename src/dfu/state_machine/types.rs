@@ -1,8 +1,14 @@
 use defmt::Format;
 
+use crate::comm_manager::transport::Origin;
 use crate::dfu::types::{DfuBlock, DfuError};
+use crate::dfu::UpdateGuard;
 
 pub struct DfuStateMachine {
+    /// The request we're currently answering. Set by the first payload of a DFU.
+    pub origin: Option<Origin>,
+    /// Held from `StartDfu` until the state machine is reset.
+    pub update: Option<UpdateGuard>,
     pub current_block: u16,
     pub total_no_blocks: u16,
     pub binary_size: usize,
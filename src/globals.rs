@@ -4,21 +4,44 @@ use embassy_sync::pubsub::PubSubChannel;
 use embassy_sync::signal::Signal;
 
 use crate::ble::types::BTHomeAD;
-use crate::comm_manager::types::CommResponse;
+use crate::comm_manager::transport::Transport;
+use crate::comm_manager::types::{CommEvent, CommMessage};
 use crate::comm_manager::types::{CommPacket, PacketError};
-use crate::sensors::types::OnboardSample;
-use crate::sensors::types::ProbeSample;
+use crate::config_manager::types::ConfigSections;
+use crate::sensors::types::OnboardReading;
+use crate::sensors::types::ProbeReading;
 
-/// Used by BLE & UART to send data to the DFU State Machine. That's why we have two publishers.
-pub static RX_BUS: PubSubChannel<ThreadModeRawMutex, Result<CommPacket, PacketError>, 3, 2, 2> =
-    PubSubChannel::new();
+/// Packets received by any transport, tagged with the transport they came in on. Only the comm
+/// manager reads them.
+pub static RX_BUS: Channel<ThreadModeRawMutex, (Transport, Result<CommPacket, PacketError>), 3> =
+    Channel::new();
+
+/// Responses to requests received over UART. See `Transport::send`.
+pub static UART_TX: Channel<ThreadModeRawMutex, CommMessage, 4> = Channel::new();
+
+/// Responses to requests received over the GATT comm service.
+pub static BLE_TX: Channel<ThreadModeRawMutex, CommMessage, 4> = Channel::new();
+
+/// Responses to payloads written to the GATT DFU service.
+pub static BLE_DFU_TX: Channel<ThreadModeRawMutex, CommMessage, 4> = Channel::new();
 
-/// Used by DFU to send data. Either via UART or BLE => that's why we have two subscribers.
-pub static TX_BUS: PubSubChannel<ThreadModeRawMutex, CommResponse, 3, 2, 2> = PubSubChannel::new();
+/// Notifications every connected host has to get, like config changes. Every transport subscribes
+/// to it. The sensor data stream doesn't go here but to the subscribed transports only, see
+/// `comm_manager::stream_sensor_data`.
+pub static EVENT_BUS: PubSubChannel<ThreadModeRawMutex, CommEvent, 4, 2, 2> = PubSubChannel::new();
+
+/// Raised for every packet received, whatever the transport. Lets the status LED blink.
+pub static COMM_ACTIVITY: Signal<ThreadModeRawMutex, Transport> = Signal::new();
 
 // These busses are used to transmit the latest onboard and probe sensor data.
-pub static ONBOARD_DATA_SIG: Signal<ThreadModeRawMutex, OnboardSample> = Signal::new();
-pub static PROBE_DATA_SIG: Signal<ThreadModeRawMutex, ProbeSample> = Signal::new();
+pub static ONBOARD_DATA_SIG: Signal<ThreadModeRawMutex, OnboardReading> = Signal::new();
+pub static PROBE_DATA_SIG: Signal<ThreadModeRawMutex, ProbeReading> = Signal::new();
 
 /// Receives advertisment payload.
 pub static BTHOME_QUEUE: Channel<ThreadModeRawMutex, BTHomeAD, 1> = Channel::new();
+
+/// Notifies the BLE, GATT, sensor and RGB tasks about which parts of the configuration changed, so that
+/// they can apply the change in place instead of restarting. Plugging in or out is announced here as
+/// well, since it changes the effective sample periods and advertising parameters.
+pub static CONFIG_CHANGED: PubSubChannel<ThreadModeRawMutex, ConfigSections, 4, 5, 2> =
+    PubSubChannel::new();
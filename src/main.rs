@@ -37,6 +37,8 @@ use static_cell::StaticCell;
 
 // Static not const since static variables have a fixed location in memory.
 static FIRMWARE_VERSION: &str = "v0.0.2";
+/// Short hash of the commit we were built from. Set by build.rs.
+static BUILD_HASH: &str = env!("SENSUS_BUILD_HASH");
 
 /// Global access to a flash
 static FLASH_DRIVER: Mutex<ThreadModeRawMutex, Option<nrf_softdevice::Flash>> = Mutex::new(None);
@@ -66,6 +68,9 @@ async fn main_task() {
     info!("Current FW version: {:?}", FIRMWARE_VERSION);
     // Enable the softdevice.
     let sd = ble::configure_ble();
+    // The GATT services need to be registered before the SoftDevice starts running.
+    #[cfg(feature = "ble-gatt-server")]
+    let gatt_server = ble::gatt::register(sd);
     // And get the flash controller
     let flash = nrf_softdevice::Flash::take(sd);
 
@@ -108,6 +113,8 @@ async fn main_task() {
     spawner.must_spawn(sensors::soil_task(probe_per));
     spawner.must_spawn(ble::payload_manager::payload_mgr_task());
     spawner.must_spawn(ble::ble_task());
+    #[cfg(feature = "ble-gatt-server")]
+    spawner.must_spawn(ble::gatt::gatt_task(gatt_server));
 
     // This "task" can run all the time, since we want DFU to be available via Bluetooth, as
     // well.
@@ -115,11 +122,15 @@ async fn main_task() {
     spawner.must_spawn(comm_manager::comm_task());
 
     // Will handle UART DFU and data logging over UART.
-    spawner.must_spawn(serial::tasks::serial_task(
-        p.UARTE0,
-        p.P0_03.degrade(),
-        p.P0_02.degrade(),
-    ));
+    let serial_per = serial::types::SerialPeripherals {
+        pin_tx: p.P0_03.degrade(),
+        pin_rx: p.P0_02.degrade(),
+        instance_uarte: p.UARTE0,
+        instance_timer: p.TIMER3,              // counts received bytes
+        instance_ppi_ch1: p.PPI_CH1.degrade(), // PPI channel
+        instance_ppi_ch2: p.PPI_CH2.degrade(), // PPI channel
+    };
+    spawner.must_spawn(serial::tasks::serial_task(serial_per));
 
     spawner.must_spawn(rgb::rgb_task(
         p.PWM0,
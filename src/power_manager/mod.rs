@@ -20,8 +20,8 @@ use embassy_sync::{
 };
 
 use crate::{
-    common,
-    config_manager::{self},
+    config_manager::{self, types::ConfigSections},
+    globals::CONFIG_CHANGED,
 };
 
 /// This structure is a `MultiWaker`. Using a multiwaker with capacity N, I can
@@ -104,8 +104,10 @@ async fn power_hook() {
 
         POWER_WAKER.wake(); // Wake any async task waiting for a power state change.
 
-        // Reset state machines
-        common::restart_state_machines();
+        // The sample periods and advertising parameters depend on the power state.
+        CONFIG_CHANGED
+            .immediate_publisher()
+            .publish_immediate(ConfigSections::SAMPLING_PERIOD | ConfigSections::ADVERTISING);
     }
 }
 
@@ -1,5 +1,5 @@
 use defmt::unwrap;
-use embassy_futures::select::select;
+use embassy_futures::select::{select, select3, Either3};
 use embassy_nrf::pwm::{
     Prescaler, SequenceConfig, SequenceLoad, SequencePwm, SingleSequenceMode, SingleSequencer,
 };
@@ -7,7 +7,7 @@ use embassy_time::{Duration, Timer};
 use heapless::Vec;
 
 use crate::{
-    globals::RX_BUS,
+    globals::{COMM_ACTIVITY, CONFIG_CHANGED},
     power_manager::{wait_for_hp, wait_for_lp},
 };
 
@@ -178,7 +178,7 @@ pub async fn rgb_task(
     mut pin_green: embassy_nrf::gpio::AnyPin,
     mut pin_blue: embassy_nrf::gpio::AnyPin,
 ) {
-    let mut data_rx = RX_BUS
+    let mut config_rx = CONFIG_CHANGED
         .dyn_subscriber()
         .expect("Failed to acquire subscriber.");
     defmt::info!("Started RGB task");
@@ -200,15 +200,20 @@ pub async fn rgb_task(
                     .await;
             }
         }
+        // Config changes that happened while on battery (including the one caused by plugging in)
+        // are old news by now.
+        while config_rx.try_next_message_pure().is_some() {}
+        COMM_ACTIVITY.reset();
         select(wait_for_lp(), async {
             loop {
-                match select(
-                    data_rx.next_message(),
+                match select3(
+                    COMM_ACTIVITY.wait(),
+                    config_rx.next_message_pure(),
                     Timer::after(Duration::from_millis(500)),
                 )
                 .await
                 {
-                    embassy_futures::select::Either::First(_) => {
+                    Either3::First(_) => {
                         let mut statusled =
                             StatusLed::new(&mut pwm, &mut pin_red, &mut pin_green, &mut pin_blue);
                         statusled
@@ -218,7 +223,18 @@ pub async fn rgb_task(
                             .transition_value(RgbValue::off(), Duration::from_millis(100))
                             .await;
                     }
-                    embassy_futures::select::Either::Second(_) => {
+                    Either3::Second(_) => {
+                        // Acknowledge the config change.
+                        let mut statusled =
+                            StatusLed::new(&mut pwm, &mut pin_red, &mut pin_green, &mut pin_blue);
+                        statusled
+                            .transition_value(RgbValue::blue(), Duration::from_millis(100))
+                            .await;
+                        statusled
+                            .transition_value(RgbValue::off(), Duration::from_millis(100))
+                            .await;
+                    }
+                    Either3::Third(_) => {
                         // TODO: Some kind of connected status, maybe?
                     }
                 };
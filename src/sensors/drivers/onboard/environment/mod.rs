@@ -5,6 +5,7 @@ use embassy_nrf::gpio::{AnyPin, Input};
 use embassy_time::Delay;
 use opt300x_async::{IntegrationTime, Opt300x, SlaveAddr};
 
+use crate::config_manager::types::EnabledSensors;
 use crate::sensors::types::Error;
 
 use self::types::EnvironmentSample;
@@ -20,32 +21,40 @@ pub fn reset(i2c_bus: super::types::BusManagerType<'_>) -> Result<(), Error> {
     Ok(())
 }
 
+/// Samples the enabled onboard environment sensors. Values of disabled sensors are left at 0.
 pub async fn sample_environment(
     i2c_bus: super::types::BusManagerType<'_>,
     mut wait_pin: Input<'_, AnyPin>,
+    sensors: &EnabledSensors,
 ) -> Result<EnvironmentSample, Error> {
+    let mut sample = EnvironmentSample::default();
     let mut shtc3 = shtc3_async::Shtc3::new(i2c_bus.acquire_i2c());
     let mut opt3001 = Opt300x::new_opt3001(i2c_bus.acquire_i2c(), SlaveAddr::Default);
-    opt3001
-        .set_integration_time(IntegrationTime::Ms100)
-        .map_err(|_| Error::OPTComm)?;
-    opt3001
-        .enable_end_of_conversion_mode()
-        .map_err(|_| Error::OPTComm)?;
-
-    let shtc3_result = shtc3.sample(&mut Delay).await.map_err(|_| Error::SHTComm)?;
-    let opt3001_result = opt3001
-        .read_lux(&mut wait_pin)
-        .await
-        .map_err(|_| Error::OPTComm)?;
-    let temperature = shtc3_result.temperature.as_degrees_celsius();
-    let humidity = shtc3_result.humidity.as_percent();
-
-    let _x = shtc3.sleep();
-
-    Ok(EnvironmentSample {
-        illuminance: opt3001_result.result,
-        temperature,
-        humidity,
-    })
+
+    if sensors.opt3001 {
+        opt3001
+            .set_integration_time(IntegrationTime::Ms100)
+            .map_err(|_| Error::OPTComm)?;
+        opt3001
+            .enable_end_of_conversion_mode()
+            .map_err(|_| Error::OPTComm)?;
+    }
+
+    if sensors.shtc3 {
+        let shtc3_result = shtc3.sample(&mut Delay).await.map_err(|_| Error::SHTComm)?;
+        sample.temperature = shtc3_result.temperature.as_degrees_celsius();
+        sample.humidity = shtc3_result.humidity.as_percent();
+
+        let _x = shtc3.sleep();
+    }
+
+    if sensors.opt3001 {
+        let opt3001_result = opt3001
+            .read_lux(&mut wait_pin)
+            .await
+            .map_err(|_| Error::OPTComm)?;
+        sample.illuminance = opt3001_result.result;
+    }
+
+    Ok(sample)
 }
@@ -1,7 +1,5 @@
 pub mod types;
 
-use core::ops::{Add, Mul, Sub};
-
 use crate::config_manager::SENSUS_CONFIG;
 use crate::sensors::drivers::frequency::types::FrequencySensor;
 use crate::sensors::types::Error;
@@ -20,43 +18,6 @@ use embassy_time::{Duration, Timer};
 
 use types::ProbeHardware;
 
-// Necessary implementations to be able to filter the data.
-impl Add for ProbeSample {
-    type Output = Self;
-
-    fn add(self, rhs: Self) -> Self::Output {
-        ProbeSample {
-            moisture: self.moisture + rhs.moisture,
-            temperature: self.temperature + rhs.temperature,
-            moisture_raw: self.moisture_raw + rhs.moisture_raw,
-        }
-    }
-}
-
-impl Sub for ProbeSample {
-    type Output = Self;
-
-    fn sub(self, rhs: Self) -> Self::Output {
-        ProbeSample {
-            moisture: self.moisture - rhs.moisture,
-            temperature: self.temperature - rhs.temperature,
-            moisture_raw: self.moisture_raw - rhs.moisture_raw,
-        }
-    }
-}
-
-impl Mul<f32> for ProbeSample {
-    type Output = Self;
-
-    fn mul(self, rhs: f32) -> Self::Output {
-        ProbeSample {
-            moisture: self.moisture * rhs,
-            temperature: self.temperature * rhs,
-            moisture_raw: self.moisture_raw * rhs,
-        }
-    }
-}
-
 bind_interrupts!(struct I2cIrqs {
     SPIM1_SPIS1_TWIM1_TWIS1_SPI1_TWI1 => twim::InterruptHandler<peripherals::TWISPI1>;
 });
@@ -1,20 +1,63 @@
 mod types;
 
+use core::sync::atomic::Ordering::Relaxed;
+
 use defmt::{error, trace};
+use embassy_futures::select::{select, Either};
 use embassy_time::{with_timeout, Duration};
 use embassy_time::{Ticker, Timer};
 
+use crate::config_manager::types::ConfigSections;
+use crate::config_manager::SENSUS_CONFIG;
 use crate::globals::ONBOARD_DATA_SIG;
 use crate::sensors::drivers::onboard::battery;
 use crate::sensors::drivers::onboard::environment;
 use crate::sensors::drivers::onboard::types::OnboardHardware;
 use crate::sensors::types::OnboardPeripherals;
+use crate::sensors::types::OnboardReading;
 use crate::sensors::types::OnboardSample;
 use crate::sensors::types::{Error, OnboardFilter};
 use crate::sensors::ONBOARD_SAMPLE_PERIOD;
 
 use types::{OnboardSM, OnboardSMState};
 
+/// Applies a config change in place. The filters keep their values, only the sample period, the
+/// filter constants and the set of sampled sensors change.
+async fn apply_config(
+    sm: &mut OnboardSM,
+    onboard_data: &mut OnboardFilter,
+    sections: ConfigSections,
+) {
+    let relevant =
+        ConfigSections::SAMPLING_PERIOD | ConfigSections::FILTER | ConfigSections::SENSORS;
+    if !sections.intersects(relevant) {
+        return;
+    }
+
+    let config = SENSUS_CONFIG.lock().await.clone().unwrap_or_default();
+    sm.sensors = config.sensors;
+    if !sm.sensors.any_onboard() {
+        defmt::info!("All onboard sensors disabled in config.");
+        sm.state = OnboardSMState::Disabled;
+        return;
+    }
+    if let OnboardSMState::Disabled = sm.state {
+        // The sensors were just re-enabled, so they need to be reset first.
+        sm.state = OnboardSMState::Start;
+        return;
+    }
+
+    let period = ONBOARD_SAMPLE_PERIOD.load(Relaxed);
+    if sections.intersects(ConfigSections::SAMPLING_PERIOD) {
+        sm.ticker = Ticker::every(Duration::from_millis(period as u64));
+    }
+    onboard_data.retune(
+        period,
+        config.filter.environment_settling_ms,
+        config.filter.battery_settling_ms,
+    );
+}
+
 /// Executes one tick of the state-machine and returns the errors if any.
 async fn tick(
     sm: &mut OnboardSM,
@@ -23,11 +66,23 @@ async fn tick(
 ) -> Result<(), Error> {
     match sm.state {
         OnboardSMState::Start => {
-            // Load sample period from config.
-            // let mutex = SENSUS_CONFIG.lock().await;
-            // let config = mutex.clone().unwrap_or_default();
-            let period = ONBOARD_SAMPLE_PERIOD.load(core::sync::atomic::Ordering::Relaxed) as u64;
-            sm.ticker = Ticker::every(Duration::from_millis(period));
+            // Load the enabled sensors from config.
+            let config = SENSUS_CONFIG.lock().await.clone().unwrap_or_default();
+            sm.sensors = config.sensors;
+            if !sm.sensors.any_onboard() {
+                defmt::info!("All onboard sensors disabled in config.");
+                sm.state = OnboardSMState::Disabled;
+                return Ok(());
+            }
+
+            let period = ONBOARD_SAMPLE_PERIOD.load(Relaxed);
+            sm.ticker = Ticker::every(Duration::from_millis(period as u64));
+            // Derive the filter constants from the sample period, so the smoothing stays the same.
+            *onboard_data = OnboardFilter::with_settling_times(
+                period,
+                config.filter.environment_settling_ms,
+                config.filter.battery_settling_ms,
+            );
 
             // Initialize hardware.
             let hw = OnboardHardware::from_peripherals(per);
@@ -38,12 +93,16 @@ async fn tick(
             sm.state = OnboardSMState::Measure;
         }
         OnboardSMState::Measure => {
+            let sensors = sm.sensors;
             let sample = with_timeout(Duration::from_millis(200), async {
                 let hw = OnboardHardware::from_peripherals(per);
 
                 let environment_data =
-                    environment::sample_environment(hw.i2c_bus, hw.wait_pin).await?;
-                let battery_level = battery::sample_battery_level(hw.battery).await;
+                    environment::sample_environment(hw.i2c_bus, hw.wait_pin, &sensors).await?;
+                let battery_level = match sensors.battery {
+                    true => battery::sample_battery_level(hw.battery).await,
+                    false => Default::default(),
+                };
 
                 let sample = OnboardSample {
                     environment_data,
@@ -64,15 +123,24 @@ async fn tick(
                 onboard_data.get_value()
             );
 
-            sm.state = OnboardSMState::Publish(onboard_data.get_value());
+            sm.state = OnboardSMState::Publish(OnboardReading {
+                raw: sample,
+                filtered: onboard_data.get_value(),
+            });
         }
-        OnboardSMState::Publish(sample) => {
-            ONBOARD_DATA_SIG.signal(sample);
+        OnboardSMState::Publish(reading) => {
+            ONBOARD_DATA_SIG.signal(reading);
             sm.state = OnboardSMState::Sleep;
         }
         OnboardSMState::Sleep => {
-            sm.ticker.next().await;
-            sm.state = OnboardSMState::Measure;
+            match select(sm.ticker.next(), sm.config_rx.next_message_pure()).await {
+                Either::First(_) => sm.state = OnboardSMState::Measure,
+                Either::Second(sections) => apply_config(sm, onboard_data, sections).await,
+            }
+        }
+        OnboardSMState::Disabled => {
+            let sections = sm.config_rx.next_message_pure().await;
+            apply_config(sm, onboard_data, sections).await;
         }
     };
 
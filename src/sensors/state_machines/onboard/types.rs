@@ -1,19 +1,27 @@
 use defmt::Format;
+use embassy_sync::pubsub::DynSubscriber;
 use embassy_time::{Duration, Ticker};
 
-use crate::sensors::types::OnboardSample;
+use crate::config_manager::types::{ConfigSections, EnabledSensors};
+use crate::globals::CONFIG_CHANGED;
+use crate::sensors::types::OnboardReading;
 
 #[derive(Format)]
 pub enum OnboardSMState {
     Start,
     Measure,
-    Publish(OnboardSample),
+    Publish(OnboardReading),
     Sleep,
+    /// All onboard sensors were disabled in the config. Stays here until one gets re-enabled.
+    Disabled,
 }
 
 pub struct OnboardSM {
     pub state: OnboardSMState,
     pub ticker: Ticker,
+    pub sensors: EnabledSensors,
+    /// Notifies us about config changes, so they can be applied without restarting.
+    pub config_rx: DynSubscriber<'static, ConfigSections>,
 }
 
 impl OnboardSM {
@@ -21,6 +29,8 @@ impl OnboardSM {
         OnboardSM {
             state: OnboardSMState::Start,
             ticker: Ticker::every(Duration::from_secs(10)),
+            sensors: Default::default(),
+            config_rx: defmt::unwrap!(CONFIG_CHANGED.dyn_subscriber()),
         }
     }
 
@@ -1,18 +1,50 @@
 mod types;
 
+use core::sync::atomic::Ordering::Relaxed;
+
 use defmt::{error, trace};
+use embassy_futures::select::{select, Either};
 use embassy_time::{with_timeout, Duration, Ticker, Timer};
 
 use crate::{
+    config_manager::{types::ConfigSections, SENSUS_CONFIG},
     globals::PROBE_DATA_SIG,
     sensors::drivers::probe::types::ProbeHardware,
     sensors::types::ProbePeripherals,
-    sensors::types::{Error, ProbeFilter},
+    sensors::types::{Error, ProbeFilter, ProbeReading},
     sensors::{drivers::probe::sample_soil, PROBE_SAMPLE_PERIOD},
 };
 
 use types::{ProbeSM, ProbeSMState};
 
+/// Applies a config change in place. The filter keeps its value, only the sample period and the
+/// filter constant change.
+async fn apply_config(sm: &mut ProbeSM, probe_data: &mut ProbeFilter, sections: ConfigSections) {
+    let relevant =
+        ConfigSections::SAMPLING_PERIOD | ConfigSections::FILTER | ConfigSections::SENSORS;
+    if !sections.intersects(relevant) {
+        return;
+    }
+
+    let config = SENSUS_CONFIG.lock().await.clone().unwrap_or_default();
+    if !config.sensors.probe {
+        defmt::info!("Probe disabled in config.");
+        sm.state = ProbeSMState::Disabled;
+        return;
+    }
+    if let ProbeSMState::Disabled = sm.state {
+        // The probe was just re-enabled, so it needs the full startup sequence.
+        sm.state = ProbeSMState::Start;
+        return;
+    }
+
+    let period = PROBE_SAMPLE_PERIOD.load(Relaxed);
+    if sections.intersects(ConfigSections::SAMPLING_PERIOD) {
+        sm.ticker = Ticker::every(Duration::from_millis(period as u64));
+    }
+    probe_data.retune(period, config.filter.probe_settling_ms);
+}
+
 /// Executes one tick of the state-machine and returns the errors if any.
 async fn tick(
     sm: &mut ProbeSM,
@@ -22,8 +54,17 @@ async fn tick(
     match sm.state {
         ProbeSMState::Start => {
             // Get probe configuration data from global config
-            let period = PROBE_SAMPLE_PERIOD.load(core::sync::atomic::Ordering::Relaxed) as u64;
-            sm.ticker = Ticker::every(Duration::from_millis(period));
+            let config = SENSUS_CONFIG.lock().await.clone().unwrap_or_default();
+            if !config.sensors.probe {
+                defmt::info!("Probe disabled in config.");
+                sm.state = ProbeSMState::Disabled;
+                return Ok(());
+            }
+
+            let period = PROBE_SAMPLE_PERIOD.load(Relaxed);
+            sm.ticker = Ticker::every(Duration::from_millis(period as u64));
+            // Derive the filter constant from the sample period, so the smoothing stays the same.
+            *probe_data = ProbeFilter::with_settling_time(period, config.filter.probe_settling_ms);
 
             // Just to be safe, keep the power line of the probe low for a bit on first start.
             // This should reset any attached circuits.
@@ -51,15 +92,27 @@ async fn tick(
                 probe_data.get_value()
             );
 
-            sm.state = ProbeSMState::Publish(probe_data.get_value().unwrap_or_default());
+            sm.state = ProbeSMState::Publish(ProbeReading {
+                raw: sample,
+                filtered: probe_data.get_value().unwrap_or_default(),
+            });
         }
-        ProbeSMState::Publish(sample) => {
-            PROBE_DATA_SIG.signal(sample);
+        ProbeSMState::Publish(reading) => {
+            PROBE_DATA_SIG.signal(reading);
             sm.state = ProbeSMState::Sleep;
         }
         ProbeSMState::Sleep => {
-            sm.ticker.next().await;
-            sm.state = ProbeSMState::Measure;
+            match select(sm.ticker.next(), sm.config_rx.next_message_pure()).await {
+                Either::First(_) => sm.state = ProbeSMState::Measure,
+                Either::Second(sections) => apply_config(sm, probe_data, sections).await,
+            }
+        }
+        ProbeSMState::Disabled => {
+            // Keep the probe unpowered until the config says otherwise.
+            let mut hw = ProbeHardware::from_peripherals(per);
+            hw.output_probe_enable.set_low();
+            let sections = sm.config_rx.next_message_pure().await;
+            apply_config(sm, probe_data, sections).await;
         }
     };
 
@@ -1,20 +1,27 @@
 use defmt::Format;
+use embassy_sync::pubsub::DynSubscriber;
 use embassy_time::{Duration, Ticker};
 
-use crate::sensors::types::ProbeSample;
+use crate::config_manager::types::ConfigSections;
+use crate::globals::CONFIG_CHANGED;
+use crate::sensors::types::ProbeReading;
 
 #[derive(Format)]
 pub enum ProbeSMState {
     /// Startup code. Should only run once.
     Start,
     Measure,
-    Publish(ProbeSample),
+    Publish(ProbeReading),
     Sleep,
+    /// The probe was disabled in the config. Stays here until it gets re-enabled.
+    Disabled,
 }
 
 pub struct ProbeSM {
     pub state: ProbeSMState,
     pub ticker: Ticker,
+    /// Notifies us about config changes, so they can be applied without restarting.
+    pub config_rx: DynSubscriber<'static, ConfigSections>,
 }
 
 impl ProbeSM {
@@ -22,6 +29,7 @@ impl ProbeSM {
         ProbeSM {
             state: ProbeSMState::Start,
             ticker: Ticker::every(Duration::from_secs(10)),
+            config_rx: defmt::unwrap!(CONFIG_CHANGED.dyn_subscriber()),
         }
     }
 
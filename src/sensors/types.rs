@@ -7,8 +7,12 @@ use embassy_nrf::{
 };
 use serde::Serialize;
 
-use crate::sensors::drivers::onboard::environment::types::EnvironmentSample;
-use crate::{common::types::Filter, sensors::drivers::onboard::battery::types::BatteryLevel};
+use crate::common::types::Filter;
+
+pub use sensus_protocol::sensors::{
+    BatteryLevel, EnvironmentSample, OnboardReading, OnboardSample, ProbeReading, ProbeSample,
+    Reading, SensorDataRaw,
+};
 
 #[derive(Format, Debug, Clone, Copy)]
 pub enum Error {
@@ -32,12 +36,6 @@ pub struct OnboardPeripherals {
     pub instance_saadc: SAADC,
 }
 
-#[derive(Serialize, Format, Clone, Copy, Default)]
-pub struct OnboardSample {
-    pub environment_data: EnvironmentSample,
-    pub battery_level: BatteryLevel,
-}
-
 #[derive(Serialize, Format, Clone)]
 pub struct OnboardFilter {
     env_filter: Filter<EnvironmentSample>,
@@ -63,37 +61,8 @@ pub struct ProbePeripherals {
     pub instance_ppi: AnyConfigurableChannel,
 }
 
-#[derive(Serialize, Format, Clone, Copy, Default)]
-pub struct ProbeSample {
-    pub moisture: f32,     // 0 - 100%
-    pub moisture_raw: f32, // Raw frequency measurement [Hz]
-    pub temperature: f32,  // °C
-}
-
 pub type ProbeFilter = Filter<ProbeSample>;
 
-#[derive(Serialize, Format, Clone, Default)]
-pub struct SensorDataRaw {
-    onboard: OnboardSample,
-    probe: ProbeSample,
-}
-
-impl SensorDataRaw {
-    pub fn with_onboard(self, onboard_sample: OnboardSample) -> Self {
-        Self {
-            onboard: onboard_sample,
-            probe: self.probe,
-        }
-    }
-
-    pub fn with_probe(self, probe_sample: ProbeSample) -> Self {
-        Self {
-            onboard: self.onboard,
-            probe: probe_sample,
-        }
-    }
-}
-
 //
 // Implementations
 //
@@ -116,6 +85,25 @@ impl OnboardFilter {
         }
     }
 
+    /// Creates the onboard filters out of the configured settling times. See
+    /// `Filter::with_settling_time`.
+    pub fn with_settling_times(
+        sample_period_ms: u32,
+        env_settling_ms: u32,
+        bat_settling_ms: u32,
+    ) -> Self {
+        OnboardFilter {
+            env_filter: Filter::with_settling_time(sample_period_ms, env_settling_ms),
+            bat_filter: Filter::with_settling_time(sample_period_ms, bat_settling_ms),
+        }
+    }
+
+    /// Retunes both filters while keeping their current values. See `Filter::retune`.
+    pub fn retune(&mut self, sample_period_ms: u32, env_settling_ms: u32, bat_settling_ms: u32) {
+        self.env_filter.retune(sample_period_ms, env_settling_ms);
+        self.bat_filter.retune(sample_period_ms, bat_settling_ms);
+    }
+
     pub fn feed(&mut self, data: OnboardSample) -> OnboardSample {
         OnboardSample {
             environment_data: self.env_filter.feed(data.environment_data),
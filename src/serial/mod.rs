@@ -1,117 +1,152 @@
-mod types;
+pub mod types;
 
-use crc::{Crc, CRC_16_GSM};
 use embassy_nrf::bind_interrupts;
-use embassy_nrf::interrupt::Binding;
+use embassy_nrf::buffered_uarte::{self, BufferedUarte};
 use embassy_nrf::peripherals;
 use embassy_nrf::uarte;
-use embassy_nrf::uarte::UarteRx;
-use embassy_nrf::uarte::UarteTx;
+use embassy_time::{with_timeout, Duration, Instant};
+use embedded_io::asynch::{BufRead, Write};
 use heapless::Vec;
-use postcard::from_bytes_cobs;
-use postcard::to_slice_cobs;
-use postcard::to_vec;
 
+use crate::comm_manager::framing;
+use crate::comm_manager::types::CommMessage;
 use crate::comm_manager::types::CommPacket;
-use crate::comm_manager::types::CommPacketType;
-use crate::comm_manager::types::CommResponse;
 use crate::comm_manager::types::PacketError;
 
-use types::UartError;
+use core::sync::atomic::{AtomicU32, Ordering::Relaxed};
+
+use types::{SerialPeripherals, UartError};
 
 pub mod tasks;
 
-pub const CRC_GSM: Crc<u16> = Crc::<u16>::new(&CRC_16_GSM);
+/// Time a frame may take from its first byte to its terminating 0x00, in milliseconds. Mirrors
+/// the config, see `config_manager::refresh_config`.
+pub static FRAME_TIMEOUT_MS: AtomicU32 = AtomicU32::new(500);
+/// Longest pause allowed between two bytes of a frame, in milliseconds. Mirrors the config.
+pub static BYTE_TIMEOUT_MS: AtomicU32 = AtomicU32::new(50);
 
 bind_interrupts!(struct UartIrqs {
-    UARTE0_UART0 => uarte::InterruptHandler<peripherals::UARTE0>;
+    UARTE0_UART0 => buffered_uarte::InterruptHandler<peripherals::UARTE0>;
 });
 
+/// Size of the ring buffer the UARTE receives into. Holds a few frames, so reception goes on while
+/// a DFU block is being written to flash.
+const RX_BUFFER_SIZE: usize = 1024;
+const TX_BUFFER_SIZE: usize = 256;
+/// Largest COBS frame we accept from the host.
+const MAX_RX_FRAME_SIZE: usize = 256;
+
 /// Initializes the UART peripheral with our default config.
-fn serial_init<'d, T>(
-    instance: &'d mut T,
-    pin_tx: &'d mut embassy_nrf::gpio::AnyPin,
-    pin_rx: &'d mut embassy_nrf::gpio::AnyPin,
-) -> (UarteTx<'d, T>, UarteRx<'d, T>)
-where
-    T: embassy_nrf::uarte::Instance,
-    UartIrqs: Binding<
-        <T as embassy_nrf::uarte::Instance>::Interrupt,
-        embassy_nrf::uarte::InterruptHandler<T>,
-    >,
-{
+///
+/// Reception runs continuously into `rx_buffer`, with the received bytes counted by the TIMER
+/// through PPI, so the CPU only wakes up to look at whole chunks of data.
+fn serial_init<'d>(
+    per: &'d mut SerialPeripherals,
+    rx_buffer: &'d mut [u8],
+    tx_buffer: &'d mut [u8],
+) -> BufferedUarte<'d, peripherals::UARTE0, peripherals::TIMER3> {
     // UART-related
     let mut config = uarte::Config::default();
     config.parity = uarte::Parity::EXCLUDED;
     config.baudrate = uarte::Baudrate::BAUD460800;
 
-    let uart = uarte::Uarte::new(instance, UartIrqs, pin_rx, pin_tx, config);
-
-    // Return the two Rx and Tx instances
-    uart.split()
+    BufferedUarte::new(
+        &mut per.instance_uarte,
+        &mut per.instance_timer,
+        &mut per.instance_ppi_ch1,
+        &mut per.instance_ppi_ch2,
+        UartIrqs,
+        &mut per.pin_rx,
+        &mut per.pin_tx,
+        config,
+        rx_buffer,
+        tx_buffer,
+    )
 }
 
-/// Reads until a 0x00 is found.
-async fn read_cobs_frame<T>(rx: &mut UarteRx<'_, T>) -> Result<Vec<u8, 256>, UartError>
-where
-    T: embassy_nrf::uarte::Instance,
-{
-    let mut buf = [0u8; 1];
-    let mut cobs_frame: Vec<u8, 256> = Vec::new();
+/// Splits the received byte stream into COBS frames. Returns the next frame, 0x00 included.
+///
+/// Waits as long as it takes for the first byte. From then on, the frame has to be completed
+/// within `FRAME_TIMEOUT_MS` and without pausing for more than `BYTE_TIMEOUT_MS`, otherwise the
+/// partial frame is discarded with `UartError::Timeout`. Frames longer than `MAX_RX_FRAME_SIZE`
+/// are skipped up to their terminator and reported as `UartError::UartBufferFull`.
+async fn read_cobs_frame<R: BufRead>(rx: &mut R) -> Result<Vec<u8, MAX_RX_FRAME_SIZE>, UartError> {
+    let mut cobs_frame: Vec<u8, MAX_RX_FRAME_SIZE> = Vec::new();
+    let mut frame_deadline = None;
+    let mut overflow = false;
     loop {
-        rx.read(&mut buf).await.map_err(|_| UartError::UartRx)?;
-        cobs_frame
-            .push(buf[0])
-            .map_err(|_| UartError::UartBufferFull)?;
-        if buf[0] == 0x00 {
+        let data = match frame_deadline {
+            None => rx.fill_buf().await.map_err(|_| UartError::UartRx)?,
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(UartError::Timeout);
+                }
+                let byte_timeout = Duration::from_millis(BYTE_TIMEOUT_MS.load(Relaxed) as u64);
+                with_timeout(byte_timeout.min(deadline - now), rx.fill_buf())
+                    .await
+                    .map_err(|_| UartError::Timeout)?
+                    .map_err(|_| UartError::UartRx)?
+            }
+        };
+
+        let (length, complete) = match data.iter().position(|&byte| byte == 0x00) {
+            Some(end) => (end + 1, true),
+            None => (data.len(), false),
+        };
+        if !overflow && cobs_frame.extend_from_slice(&data[..length]).is_err() {
+            overflow = true;
+        }
+        rx.consume(length);
+
+        if complete {
             // Got end-of-frame.
-            return Ok(cobs_frame);
+            return match overflow {
+                true => Err(UartError::UartBufferFull),
+                false => Ok(cobs_frame),
+            };
+        }
+        if frame_deadline.is_none() {
+            let frame_timeout = Duration::from_millis(FRAME_TIMEOUT_MS.load(Relaxed) as u64);
+            frame_deadline = Some(Instant::now() + frame_timeout);
         }
     }
 }
 
-fn calculate_checksum(content: &CommPacketType) -> Result<u16, PacketError> {
-    // TODO. The 256 byte limit should not be hard-coded. It should depend on the size of the structure
-    let serialized: Vec<u8, 256> = to_vec(content).map_err(|_| PacketError::PacketCRC)?;
-    let crc = CRC_GSM.checksum(serialized.as_slice());
-    Ok(crc)
-}
-
 /// Waits for a COBS-encoded packet on UART and tries to transform it into a CommPacket.
 ///
-/// * `rx` - Mutable reference to a UarteRx peripheral.
-/// * `timeout` - TODO Duration after which a timeout error will be reported.
-async fn recv_packet<T>(rx: &mut UarteRx<'_, T>) -> Result<CommPacket, PacketError>
-where
-    T: embassy_nrf::uarte::Instance,
-{
-    let mut raw_data = read_cobs_frame(rx)
-        .await
-        .map_err(|_| PacketError::PhysError)?;
-
-    let packet: CommPacket =
-        from_bytes_cobs(&mut raw_data).map_err(|_| PacketError::DeserializationError)?;
-
-    // Extract the checksum and check if it's a fine checksum
-    let checksum = packet.crc;
-    let actual_checksum = calculate_checksum(&packet.payload)?;
-    if checksum != actual_checksum {
-        defmt::error!("Checksum error");
-        return Err(PacketError::PacketCRC);
-    }
-
-    Ok(packet)
+/// * `rx` - Receiving half of the buffered UARTE.
+async fn recv_packet<R: BufRead>(rx: &mut R) -> Result<CommPacket, PacketError> {
+    let mut raw_data = read_cobs_frame(rx).await.map_err(|err| match err {
+        UartError::Timeout => PacketError::Timeout,
+        _ => PacketError::PhysError,
+    })?;
+
+    framing::decode_packet(&mut raw_data)
 }
 
-/// Sends a COBS-encoded packet over UART.
-async fn send_response<T>(tx: &mut UarteTx<'_, T>, response: CommResponse) -> Result<(), UartError>
-where
-    T: embassy_nrf::uarte::Instance,
-{
-    let mut buf = [0u8; 64]; // 64 bytes should be enough to encode any reply of ours.
-    let tx_buf = to_slice_cobs(&response, &mut buf).expect("COBS encoding error.");
-
-    tx.write(tx_buf).await.map_err(|_| UartError::UartTx)?;
+/// Sends a message over UART, split into as many COBS-encoded frames as needed.
+///
+/// * `number` - Message number of the fragments. Increment it for every message.
+async fn send_response<W: Write>(
+    tx: &mut W,
+    response: &CommMessage,
+    number: u8,
+) -> Result<(), UartError> {
+    let mut message_buf = [0u8; framing::MAX_MESSAGE_SIZE];
+    let fragments = framing::fragment_message(
+        response,
+        number,
+        framing::MAX_FRAGMENT_SIZE,
+        &mut message_buf,
+    )
+    .map_err(UartError::Encoding)?;
+
+    for fragment in fragments {
+        let mut buf = [0u8; framing::MAX_FRAME_SIZE];
+        let tx_buf = framing::encode_fragment(&fragment, &mut buf).map_err(UartError::Encoding)?;
+        tx.write_all(tx_buf).await.map_err(|_| UartError::UartTx)?;
+    }
 
     Ok(())
 }
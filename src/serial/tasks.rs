@@ -1,61 +1,75 @@
 use defmt::error;
 use embassy_futures::join::join;
-use embassy_futures::select::select;
+use embassy_futures::select::{select, Either};
+use embassy_sync::pubsub::WaitResult;
 
 use super::serial_init;
-use crate::globals::{RX_BUS, TX_BUS};
+use crate::comm_manager::set_sensor_stream;
+use crate::comm_manager::transport::Transport;
+use crate::comm_manager::types::CommMessage;
+use crate::globals::{EVENT_BUS, RX_BUS, UART_TX};
 use crate::power_manager::{self};
-use embassy_nrf::gpio::AnyPin;
-use embassy_nrf::peripherals;
-use embassy_nrf::peripherals::UARTE0;
-use embassy_nrf::uarte::UarteRx;
-use embassy_nrf::uarte::UarteTx;
+use embedded_io::asynch::{BufRead, Write};
 
-use super::{recv_packet, send_response};
+use super::types::{SerialPeripherals, UartError};
+use super::{recv_packet, send_response, RX_BUFFER_SIZE, TX_BUFFER_SIZE};
+use crate::comm_manager::framing;
 
-async fn uart_rx_task(rx: &mut UarteRx<'_, UARTE0>) {
+async fn uart_rx_task<R: BufRead>(rx: &mut R) {
     loop {
         let raw_packet_res = recv_packet(rx).await;
-        RX_BUS
-            .immediate_publisher()
-            .publish_immediate(raw_packet_res);
+        RX_BUS.send((Transport::Uart, raw_packet_res)).await;
     }
 }
 
-async fn uart_tx_task(tx: &mut UarteTx<'_, UARTE0>) {
-    let mut subscriber = TX_BUS
+/// Sends the responses to our own requests, and the events meant for every host.
+async fn uart_tx_task<W: Write>(tx: &mut W) {
+    let mut events = EVENT_BUS
         .subscriber()
-        .expect("Error registering subscriber for TX_BUS.");
+        .expect("Error registering subscriber for EVENT_BUS.");
+    let mut number: u8 = 0;
     loop {
-        let packet = subscriber.next_message().await;
-        match packet {
-            embassy_sync::pubsub::WaitResult::Lagged(x) => {
-                error!("Missed {:?} messages.", x);
+        let raw = match select(UART_TX.recv(), events.next_message()).await {
+            Either::First(response) => response,
+            Either::Second(WaitResult::Message(event)) => CommMessage::Event(event),
+            Either::Second(WaitResult::Lagged(x)) => {
+                error!("Missed {:?} events.", x);
+                continue;
             }
-            embassy_sync::pubsub::WaitResult::Message(raw) => {
-                // info!("Sending packet: {:?}", raw);
-                send_response(tx, raw)
-                    .await
-                    .expect("Failed to send packet.");
+        };
+        // info!("Sending packet: {:?}", raw);
+        let mut result = send_response(tx, &raw, number).await;
+        number = number.wrapping_add(1);
+        if let Err(UartError::Encoding(err)) = result {
+            error!("Failed to encode {:?}: {:?}", raw, err);
+            if let Some(fallback) = framing::encoding_failed(&raw) {
+                result = send_response(tx, &fallback, number).await;
+                number = number.wrapping_add(1);
             }
         }
+        if let Err(err) = result {
+            error!("Failed to send packet: {:?}", err);
+        }
     }
 }
 
 #[embassy_executor::task]
-pub async fn serial_task(
-    mut instance: peripherals::UARTE0,
-    mut pin_tx: AnyPin,
-    mut pin_rx: AnyPin,
-) {
+pub async fn serial_task(mut per: SerialPeripherals) {
+    let mut rx_buffer = [0u8; RX_BUFFER_SIZE];
+    let mut tx_buffer = [0u8; TX_BUFFER_SIZE];
     loop {
         power_manager::wait_for_hp().await;
         select(power_manager::wait_for_lp(), async {
             defmt::info!("Started UART communication.");
-            let (mut tx, mut rx) = serial_init(&mut instance, &mut pin_tx, &mut pin_rx);
+            let mut uart = serial_init(&mut per, &mut rx_buffer, &mut tx_buffer);
+            let (mut rx, mut tx) = uart.split_by_ref();
+            // Answers to requests from before going to sleep are stale by now.
+            while UART_TX.try_recv().is_ok() {}
 
             join(uart_rx_task(&mut rx), uart_tx_task(&mut tx)).await;
         })
         .await;
+        // The host can't tell we went to sleep. It has to subscribe again once we're back.
+        set_sensor_stream(Transport::Uart, false);
     }
 }
@@ -1,4 +1,9 @@
 use defmt::Format;
+use embassy_nrf::gpio::AnyPin;
+use embassy_nrf::peripherals::{TIMER3, UARTE0};
+use embassy_nrf::ppi::AnyConfigurableChannel;
+
+use crate::comm_manager::framing::FramingError;
 
 #[allow(clippy::enum_variant_names)]
 #[derive(Format, Debug, Clone, Copy)]
@@ -7,4 +12,18 @@ pub enum UartError {
     UartRx,
     UartTx,
     UartBufferFull,
+    /// A frame wasn't completed in time.
+    Timeout,
+    /// A message could not be turned into frames.
+    Encoding(FramingError),
+}
+
+pub struct SerialPeripherals {
+    pub pin_tx: AnyPin,
+    pub pin_rx: AnyPin,
+    pub instance_uarte: UARTE0,
+    /// Counts the received bytes.
+    pub instance_timer: TIMER3,
+    pub instance_ppi_ch1: AnyConfigurableChannel,
+    pub instance_ppi_ch2: AnyConfigurableChannel,
 }